@@ -0,0 +1,444 @@
+//! Tile layers drawn as a few meshes instead of a sprite per tile, for
+//! [`TileRenderMode::Chunks`] (and `bevy_ecs_tilemap` tilemaps, and 3d maps).
+
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
+use tiled_parse::data_types::{Gid, LayerTile, TileAuxInfo, TileSet};
+
+use crate::error::TiledLoaderError;
+use crate::load::{locate_tile, MapSpawnContext};
+use crate::types::{ChunkTile, TileChunk, TilePos, TileRenderMode, TiledLoaderSettings};
+
+/// Whether a tile needs its own entity when its layer is drawn with chunks.
+pub(crate) fn needs_tile_entity(tile_aux_info: Option<&TileAuxInfo>) -> bool {
+    tile_aux_info
+        .is_some_and(|t| !t.class.is_empty() || !t.properties.is_empty() || !t.objects.is_empty())
+}
+
+#[cfg_attr(not(feature = "sprite3d"), allow(unused_variables))]
+pub(crate) fn chunk_tile(
+    LayerTile {
+        flip_h,
+        flip_v,
+        flip_d,
+        ..
+    }: LayerTile,
+    tileset: &TileSet,
+    index: u32,
+    settings: &TiledLoaderSettings,
+) -> ChunkTile {
+    #[cfg(feature = "sprite3d")]
+    let (elevation, side_index) =
+        crate::plane3d::tile_elevation(tileset.tile_stuff.get(&index), index, settings);
+    #[cfg(not(feature = "sprite3d"))]
+    let (elevation, side_index) = (0, index);
+
+    ChunkTile {
+        index,
+        flip_h,
+        flip_v,
+        flip_d,
+        elevation,
+        side_index,
+    }
+}
+
+/// The empty chunk `pos` belongs to, for the tiles of the tileset at `tileset_index`.
+pub(crate) fn new_chunk(
+    pos: TilePos,
+    chunk_size: u32,
+    layer_size: UVec2,
+    tileset_index: usize,
+    opacity: f32,
+    ctx: &MapSpawnContext,
+) -> TileChunk {
+    let chunk_size = chunk_size.max(1);
+    let origin = TilePos::new(
+        pos.x / chunk_size * chunk_size,
+        pos.y / chunk_size * chunk_size,
+    );
+    let size = UVec2::splat(chunk_size).min(layer_size - UVec2::new(origin.x, origin.y));
+    let tileset = &ctx.tile_sets[tileset_index];
+
+    #[cfg(feature = "sprite3d")]
+    let step_height = crate::plane3d::extrusion(ctx.settings).map_or(0., |e| e.step_height);
+    #[cfg(not(feature = "sprite3d"))]
+    let step_height = 0.;
+
+    TileChunk {
+        origin,
+        size,
+        tiles: vec![None; (size.x * size.y) as usize],
+        layout: ctx.tilemap_atlases[tileset_index].clone(),
+        cell_size: ctx.tile_size,
+        tile_size: Vec2::new(tileset.tile_size.0 as f32, tileset.tile_size.1 as f32),
+        y_sign: ctx.settings.y_sign(),
+        opacity,
+        step_height,
+    }
+}
+
+/// Spawns the entity of a chunk, as a child of its layer's. What draws it is built once it is
+/// spawned.
+pub(crate) fn spawn_chunk(
+    world: &mut World,
+    layer_ent: Entity,
+    chunk: TileChunk,
+    tileset_index: usize,
+    ctx: &MapSpawnContext,
+) {
+    let mut chunk_entity = world.spawn((
+        Name::new(format!("Chunk {}, {}", chunk.origin.x, chunk.origin.y)),
+        chunk,
+    ));
+
+    match ctx.settings.render_mode {
+        #[cfg(feature = "sprite3d")]
+        _ if ctx.settings.plane3d().is_some() => {
+            chunk_entity.insert(bevy::pbr::PbrBundle {
+                mesh: Handle::default(),
+                material: ctx.tilemap_standard_materials[tileset_index].clone(),
+                ..Default::default()
+            });
+
+            #[cfg(feature = "rapier3d_colliders")]
+            if ctx.settings.generate_colliders && crate::plane3d::extrusion(ctx.settings).is_some()
+            {
+                chunk_entity.insert(crate::plane3d::ChunkTrimeshCollider);
+            }
+        }
+        #[cfg(feature = "ecs_tilemap")]
+        TileRenderMode::EcsTilemap => {
+            chunk_entity.insert((
+                crate::ecs_tilemap::ecs_tilemap_chunk(tileset_index, ctx),
+                SpatialBundle::default(),
+            ));
+        }
+        _ => {
+            chunk_entity.insert(MaterialMesh2dBundle {
+                mesh: Mesh2dHandle::default(),
+                material: ctx.tilemap_materials[tileset_index].clone(),
+                ..Default::default()
+            });
+        }
+    }
+
+    chunk_entity.set_parent(layer_ent);
+}
+
+/// Sets the tile at `pos` in the chunks of the layer, spawning the chunk it goes in if there is
+/// none. Does nothing unless the layer is drawn with chunks.
+pub(crate) fn set_chunk_tile(
+    world: &mut World,
+    layer_ent: Entity,
+    layer_name: &str,
+    layer_size: UVec2,
+    pos: TilePos,
+    tile: Option<LayerTile>,
+    opacity: f32,
+    ctx: &MapSpawnContext,
+) -> Result<(), TiledLoaderError> {
+    let Some(size) = ctx.settings.chunk_size() else {
+        return Ok(());
+    };
+
+    let located = tile
+        .map(|tile| {
+            let Gid(gid) = tile.tile;
+            locate_tile(ctx.tile_sets, tile.tile)
+                .map(|(tileset_index, tileset, index)| {
+                    (
+                        tileset_index,
+                        chunk_tile(tile, tileset, index, ctx.settings),
+                    )
+                })
+                .ok_or_else(|| TiledLoaderError::MissingTileset {
+                    gid,
+                    layer: layer_name.into(),
+                })
+        })
+        .transpose()?;
+
+    let children = world
+        .get::<Children>(layer_ent)
+        .map(|c| c.to_vec())
+        .unwrap_or_default();
+
+    let mut placed = false;
+    for child in children {
+        let Some(mut chunk) = world.get_mut::<TileChunk>(child) else {
+            continue;
+        };
+        if !chunk.contains(pos) {
+            continue;
+        }
+
+        match located {
+            Some((tileset_index, tile)) if chunk.layout == ctx.tilemap_atlases[tileset_index] => {
+                chunk.set(pos, Some(tile));
+                placed = true;
+            }
+            _ if chunk.get(pos).is_some() => chunk.set(pos, None),
+            _ => {}
+        }
+    }
+
+    if let (false, Some((tileset_index, tile))) = (placed, located) {
+        let mut chunk = new_chunk(pos, size, layer_size, tileset_index, opacity, ctx);
+        chunk.set(pos, Some(tile));
+
+        spawn_chunk(world, layer_ent, chunk, tileset_index, ctx);
+    }
+
+    Ok(())
+}
+
+/// Builds the mesh of a chunk, in the space of its layer. Tiles are placed like their sprites
+/// would be, and raised along `+ z` by their elevation.
+pub(crate) fn chunk_mesh(chunk: &TileChunk, layout: &TextureAtlasLayout) -> Mesh {
+    // Top-left, top-right, bottom-right and bottom-left.
+    const CORNERS: [Vec2; 4] = [
+        Vec2::new(0., 0.),
+        Vec2::new(1., 0.),
+        Vec2::new(1., 1.),
+        Vec2::new(0., 1.),
+    ];
+
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut indices = Vec::new();
+
+    let mut push_quad = |corners: [Vec3; 4], texels: [Vec2; 4], rect: URect, normal: Vec3| {
+        indices.extend([0, 1, 2, 0, 2, 3].map(|i| positions.len() as u32 + i));
+
+        for (corner, texel) in corners.into_iter().zip(texels) {
+            let uv = (rect.min.as_vec2() + texel * rect.size().as_vec2()) / layout.size.as_vec2();
+
+            positions.push(corner.to_array());
+            uvs.push(uv.to_array());
+            normals.push(normal.to_array());
+        }
+    };
+
+    let tiles = (0..chunk.size.y)
+        .flat_map(|y| (0..chunk.size.x).map(move |x| (x, y)))
+        .filter_map(|(x, y)| {
+            let pos = TilePos::new(chunk.origin.x + x, chunk.origin.y + y);
+            chunk.get(pos).map(|tile| (pos, tile))
+        });
+
+    for (TilePos { x, y }, tile) in tiles {
+        let Some(rect) = layout.textures.get(tile.index as usize) else {
+            continue;
+        };
+        let height = tile.elevation as f32 * chunk.step_height;
+
+        // NOTE:
+        // Tiles are drawn from the bottom-left corner of their cell, and a diagonal flip swaps
+        // their width and height around their center.
+        let center = Vec2::new(
+            chunk.cell_size.x * x as f32 + chunk.tile_size.x / 2.,
+            chunk.cell_size.y * (y + 1) as f32 - chunk.tile_size.y / 2.,
+        );
+        let half_size = if tile.flip_d {
+            Vec2::new(chunk.tile_size.y, chunk.tile_size.x)
+        } else {
+            chunk.tile_size
+        } / 2.;
+
+        push_quad(
+            CORNERS.map(|corner| {
+                let position = center + (corner * 2. - 1.) * half_size;
+                Vec3::new(position.x, chunk.y_sign * position.y, height)
+            }),
+            // NOTE:
+            // Tiled applies the diagonal flip first, then the horizontal and vertical ones, so
+            // they are undone in the opposite order.
+            CORNERS.map(|mut texel| {
+                if tile.flip_v {
+                    texel.y = 1. - texel.y;
+                }
+                if tile.flip_h {
+                    texel.x = 1. - texel.x;
+                }
+                if tile.flip_d {
+                    texel = Vec2::new(texel.y, texel.x);
+                }
+                texel
+            }),
+            *rect,
+            Vec3::Z,
+        );
+
+        let Some(side_rect) = layout.textures.get(tile.side_index as usize) else {
+            continue;
+        };
+
+        // NOTE:
+        // Sides go down to the elevation of the neighboring tile, or to the ground next to empty
+        // cells and other chunks. Each step gets its own face, so that side tiles aren't
+        // stretched.
+        let [x0, x1] = [x, x + 1].map(|x| x as f32 * chunk.cell_size.x);
+        let [y0, y1] = [y, y + 1].map(|y| y as f32 * chunk.cell_size.y);
+
+        // Direction of each side, and its ends from left to right when facing it, in Tiled's
+        // `+ y` down space.
+        for (direction, left, right) in [
+            (IVec2::new(0, -1), Vec2::new(x1, y0), Vec2::new(x0, y0)),
+            (IVec2::new(-1, 0), Vec2::new(x0, y0), Vec2::new(x0, y1)),
+            (IVec2::new(0, 1), Vec2::new(x0, y1), Vec2::new(x1, y1)),
+            (IVec2::new(1, 0), Vec2::new(x1, y1), Vec2::new(x1, y0)),
+        ] {
+            let neighbor = x
+                .checked_add_signed(direction.x)
+                .zip(y.checked_add_signed(direction.y))
+                .and_then(|(x, y)| chunk.get(TilePos::new(x, y)))
+                .map_or(0, |neighbor| neighbor.elevation);
+            let [left, right] = [left, right].map(|p| Vec2::new(p.x, chunk.y_sign * p.y));
+            let normal = Vec3::new(direction.x as f32, chunk.y_sign * direction.y as f32, 0.);
+
+            for step in neighbor..tile.elevation {
+                let [bottom, top] = [step, step + 1].map(|s| s as f32 * chunk.step_height);
+
+                push_quad(
+                    [
+                        left.extend(top),
+                        right.extend(top),
+                        right.extend(bottom),
+                        left.extend(bottom),
+                    ],
+                    CORNERS,
+                    *side_rect,
+                    normal,
+                );
+            }
+        }
+    }
+
+    let colors = vec![[1., 1., 1., chunk.opacity]; positions.len()];
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, colors)
+    // NOTE:
+    // Only lit materials use them, 2d ones ignore them.
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    fn tile(index: u32, flip_h: bool, flip_d: bool, elevation: u32) -> ChunkTile {
+        ChunkTile {
+            index,
+            flip_h,
+            flip_v: false,
+            flip_d,
+            elevation,
+            side_index: 1,
+        }
+    }
+
+    /// Mesh of a 2x2 chunk at (2, 2).
+    fn chunk_mesh_of(
+        tiles: [Option<ChunkTile>; 4],
+        step_height: f32,
+    ) -> (Vec<[f32; 3]>, Vec<[f32; 2]>) {
+        let chunk = TileChunk {
+            origin: TilePos::new(2, 2),
+            size: UVec2::new(2, 2),
+            tiles: tiles.to_vec(),
+            layout: Handle::default(),
+            cell_size: Vec2::splat(16.),
+            tile_size: Vec2::new(16., 32.),
+            y_sign: -1.,
+            opacity: 1.,
+            step_height,
+        };
+        let layout = TextureAtlasLayout::from_grid(UVec2::new(16, 32), 2, 1, None, None);
+        let mesh = chunk_mesh(&chunk, &layout);
+
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("Missing positions");
+        };
+        let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0)
+        else {
+            panic!("Missing UVs");
+        };
+
+        (positions.clone(), uvs.clone())
+    }
+
+    fn single_tile_mesh(tile: ChunkTile) -> (Vec<[f32; 3]>, Vec<[f32; 2]>) {
+        chunk_mesh_of([None, Some(tile), None, None], 0.)
+    }
+
+    #[test]
+    fn tiles_sit_on_the_bottom_of_their_cell() {
+        let (positions, uvs) = single_tile_mesh(tile(0, false, false, 0));
+
+        // The tile at (3, 2) is twice as tall as its cell, so it reaches up into the row above.
+        assert_eq!(positions[0], [48., -16., 0.]);
+        assert_eq!(positions[2], [64., -48., 0.]);
+        assert_eq!(uvs, vec![[0., 0.], [0.5, 0.], [0.5, 1.], [0., 1.]]);
+    }
+
+    #[test]
+    fn flips_move_uvs() {
+        let (_, uvs) = single_tile_mesh(tile(1, true, false, 0));
+        assert_eq!(uvs, vec![[1., 0.], [0.5, 0.], [0.5, 1.], [1., 1.]]);
+
+        let (positions, uvs) = single_tile_mesh(tile(0, false, true, 0));
+        // Width and height swap around the tile's center.
+        assert_eq!(positions[0], [40., -24., 0.]);
+        assert_eq!(uvs, vec![[0., 0.], [0., 1.], [0.5, 1.], [0.5, 0.]]);
+    }
+
+    #[test]
+    fn raised_tiles_get_sides_down_to_their_neighbors() {
+        // Without a step height, elevations are ignored.
+        let (positions, _) = chunk_mesh_of([None, Some(tile(0, false, false, 2)), None, None], 0.);
+        assert_eq!(positions.len(), 4);
+
+        // A tile raised by 2 steps next to one raised by 1, the other cells being empty.
+        let (positions, uvs) = chunk_mesh_of(
+            [
+                Some(tile(0, false, false, 2)),
+                Some(tile(0, false, false, 1)),
+                None,
+                None,
+            ],
+            0.5,
+        );
+
+        // Each tile has a top. The first one has 2 steps of sides on 3 sides and 1 towards its
+        // neighbor, the second one 1 step on 3 sides.
+        assert_eq!(positions.len(), 4 * (2 + 2 * 3 + 1 + 3));
+        assert_eq!(positions[0][2], 1.);
+
+        // The first side is the north one of the first tile, its bottom step first, read from
+        // east to west.
+        assert_eq!(
+            positions[4..8],
+            [
+                [48., -32., 0.5],
+                [32., -32., 0.5],
+                [32., -32., 0.],
+                [48., -32., 0.]
+            ]
+        );
+        assert_eq!(uvs[4..8], [[0.5, 0.], [1., 0.], [1., 1.], [0.5, 1.]]);
+    }
+}
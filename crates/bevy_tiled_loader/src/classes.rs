@@ -0,0 +1,296 @@
+//! Tiled classes turned into components.
+//!
+//! Objects, tiles and layers whose class was registered with
+//! [`register_tiled_class`](crate::plugin::TiledClassAppExt::register_tiled_class) get the
+//! class' component, its fields filled from their properties of the same name.
+
+use std::any::TypeId;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use bevy::ecs::reflect::ReflectComponent;
+use bevy::prelude::{Component, EntityWorldMut, Resource};
+use bevy::reflect::std_traits::ReflectDefault;
+use bevy::reflect::{
+    DynamicEnum, DynamicStruct, DynamicVariant, Reflect, ReflectFromReflect, TypeInfo,
+    TypeRegistry, VariantInfo,
+};
+use bevy::utils::hashbrown::HashMap;
+use tiled_parse::data_types::{Properties, TiledPropertyType};
+
+use crate::error::TiledLoaderError;
+
+/// Component types of Tiled classes, by class name.
+///
+/// Shared with [`TiledLoader`](crate::load::TiledLoader), so that classes registered after the
+/// loader was created are still known to it.
+#[derive(Resource, Clone, Default)]
+pub struct TiledClassRegistry(Arc<RwLock<HashMap<String, TypeId>>>);
+
+impl TiledClassRegistry {
+    pub fn register<T: Component + Reflect>(&self, class: impl Into<String>) {
+        self.0
+            .write()
+            .unwrap()
+            .insert(class.into(), TypeId::of::<T>());
+    }
+
+    pub fn get(&self, class: &str) -> Option<TypeId> {
+        self.0.read().unwrap().get(class).copied()
+    }
+}
+
+/// Inserts the component of `class` on `entity`, filled from `properties`. Does nothing if the
+/// class wasn't registered.
+///
+/// Fields without a property keep their default value, which the component must reflect
+/// `Default` for. Properties without a field are ignored.
+pub(crate) fn insert_class_component(
+    entity: &mut EntityWorldMut,
+    class: &str,
+    properties: &Properties,
+    type_registry: &TypeRegistry,
+    classes: &TiledClassRegistry,
+) -> Result<(), TiledLoaderError> {
+    let Some(type_id) = (!class.is_empty()).then(|| classes.get(class)).flatten() else {
+        return Ok(());
+    };
+    let class_error = |reason: String| TiledLoaderError::Class {
+        class: class.into(),
+        reason,
+    };
+
+    let registration = type_registry
+        .get(type_id)
+        .ok_or_else(|| class_error("its type isn't in the type registry".into()))?;
+    let reflect_component = registration
+        .data::<ReflectComponent>()
+        .ok_or_else(|| class_error("its type doesn't reflect `Component`".into()))?;
+    let TypeInfo::Struct(info) = registration.type_info() else {
+        return Err(class_error(
+            "only structs with named fields are supported".into(),
+        ));
+    };
+
+    let mut fields = DynamicStruct::default();
+    for (name, property) in properties {
+        let Some(field) = info.field(name) else {
+            continue;
+        };
+        let value = field_value(property, field.type_id(), type_registry).ok_or_else(|| {
+            class_error(format!(
+                "property `{name}` doesn't fit in a `{}`",
+                field.type_path()
+            ))
+        })?;
+
+        fields.insert_boxed(name.as_str(), value);
+    }
+
+    let component = match registration.data::<ReflectDefault>() {
+        Some(reflect_default) => {
+            let mut component = reflect_default.default();
+            component
+                .try_apply(&fields)
+                .map_err(|e| class_error(e.to_string()))?;
+            component
+        }
+        None => registration
+            .data::<ReflectFromReflect>()
+            .and_then(|reflect_from_reflect| reflect_from_reflect.from_reflect(&fields))
+            .ok_or_else(|| {
+                class_error("properties are missing, and its type doesn't reflect `Default`".into())
+            })?,
+    };
+
+    reflect_component.insert(entity, component.as_reflect(), type_registry);
+
+    Ok(())
+}
+
+/// Converts a property to the type of the field it fills, if it makes sense.
+fn field_value(
+    property: &TiledPropertyType,
+    field: TypeId,
+    type_registry: &TypeRegistry,
+) -> Option<Box<dyn Reflect>> {
+    fn boxed<T: Reflect>(value: T) -> Box<dyn Reflect> {
+        Box::new(value)
+    }
+
+    macro_rules! integer {
+        ($v:expr => $($t:ty),*) => {
+            $(
+                if field == TypeId::of::<$t>() {
+                    return <$t>::try_from($v).ok().map(boxed);
+                }
+            )*
+        };
+    }
+
+    match property {
+        TiledPropertyType::Int(v) => {
+            integer!(*v => i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+            if field == TypeId::of::<f32>() {
+                Some(boxed(*v as f32))
+            } else if field == TypeId::of::<f64>() {
+                Some(boxed(*v as f64))
+            } else {
+                None
+            }
+        }
+        TiledPropertyType::Float(v) => {
+            if field == TypeId::of::<f32>() {
+                Some(boxed(*v))
+            } else if field == TypeId::of::<f64>() {
+                Some(boxed(*v as f64))
+            } else {
+                None
+            }
+        }
+        TiledPropertyType::Bool(v) => (field == TypeId::of::<bool>()).then(|| boxed(*v)),
+        TiledPropertyType::String(s) => {
+            if field == TypeId::of::<String>() {
+                return Some(boxed(s.clone()));
+            }
+
+            // NOTE:
+            // Tiled's enums are stored as strings, naming one of their values.
+            match type_registry.get_type_info(field) {
+                Some(TypeInfo::Enum(info))
+                    if matches!(info.variant(s), Some(VariantInfo::Unit(_))) =>
+                {
+                    Some(boxed(DynamicEnum::new(s.clone(), DynamicVariant::Unit)))
+                }
+                _ => None,
+            }
+        }
+        TiledPropertyType::File(path) => {
+            if field == TypeId::of::<PathBuf>() {
+                Some(boxed(path.clone()))
+            } else if field == TypeId::of::<String>() {
+                Some(boxed(path.to_string_lossy().into_owned()))
+            } else {
+                None
+            }
+        }
+        TiledPropertyType::Object(id) => (field == TypeId::of::<u32>()).then(|| boxed(*id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::World;
+
+    #[derive(Component, Reflect, Default, Debug, PartialEq)]
+    #[reflect(Component, Default)]
+    struct Door {
+        locked: bool,
+        key: String,
+        hits: u8,
+        speed: f32,
+        side: Side,
+    }
+
+    #[derive(Reflect, Default, Debug, PartialEq)]
+    enum Side {
+        #[default]
+        Left,
+        Right,
+    }
+
+    fn insert_door(properties: &[(&str, TiledPropertyType)]) -> Result<Option<Door>, String> {
+        let mut type_registry = TypeRegistry::default();
+        type_registry.register::<Door>();
+        type_registry.register::<Side>();
+
+        let classes = TiledClassRegistry::default();
+        classes.register::<Door>("Door");
+
+        let properties = properties
+            .iter()
+            .map(|(name, p)| (name.to_string(), p.clone()))
+            .collect();
+
+        let mut world = World::new();
+        let mut entity = world.spawn_empty();
+
+        insert_class_component(&mut entity, "Door", &properties, &type_registry, &classes)
+            .map_err(|e| e.to_string())?;
+
+        Ok(entity.take::<Door>())
+    }
+
+    #[test]
+    fn properties_fill_fields() {
+        assert_eq!(
+            insert_door(&[
+                ("locked", TiledPropertyType::Bool(true)),
+                ("key", TiledPropertyType::String("red".into())),
+                ("hits", TiledPropertyType::Int(3)),
+                ("speed", TiledPropertyType::Int(2)),
+                ("side", TiledPropertyType::String("Right".into())),
+                ("unrelated", TiledPropertyType::Float(1.)),
+            ]),
+            Ok(Some(Door {
+                locked: true,
+                key: "red".into(),
+                hits: 3,
+                speed: 2.,
+                side: Side::Right,
+            }))
+        );
+    }
+
+    #[test]
+    fn classes_registered_on_the_app_are_inserted() {
+        use bevy::prelude::{App, AppTypeRegistry};
+
+        use crate::plugin::TiledClassAppExt;
+
+        let mut app = App::new();
+        app.register_tiled_class::<Door>("Door");
+
+        let world = app.world_mut();
+        let type_registry = world.resource::<AppTypeRegistry>().clone();
+        let classes = world.resource::<TiledClassRegistry>().clone();
+        let properties = [(
+            "side".to_string(),
+            TiledPropertyType::String("Right".into()),
+        )]
+        .into_iter()
+        .collect();
+
+        let mut entity = world.spawn_empty();
+        insert_class_component(
+            &mut entity,
+            "Door",
+            &properties,
+            &type_registry.read(),
+            &classes,
+        )
+        .unwrap();
+
+        assert_eq!(
+            entity.take::<Door>(),
+            Some(Door {
+                side: Side::Right,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn missing_properties_are_defaulted() {
+        assert_eq!(insert_door(&[]), Ok(Some(Door::default())));
+    }
+
+    #[test]
+    fn mismatched_properties_are_errors() {
+        assert!(insert_door(&[("hits", TiledPropertyType::Int(300))]).is_err());
+        assert!(insert_door(&[("locked", TiledPropertyType::Int(1))]).is_err());
+        assert!(insert_door(&[("side", TiledPropertyType::String("Up".into()))]).is_err());
+    }
+}
@@ -0,0 +1,1136 @@
+//! Colliders built from the collision shapes of tiles.
+//!
+//! Shapes are first turned into a [`ColliderGeometry`], which the enabled physics backend then
+//! turns into its own collider. Colliders aren't `Reflect`, so they are stored in the scene as
+//! [`Serialized`] components.
+//!
+//! 3d backends extrude shapes along `+ z`, which is up on maps laid out on the `XZ` plane.
+
+use std::f32::consts::TAU;
+use std::str::FromStr;
+
+use bevy::hierarchy::BuildWorldChildren;
+use bevy::math::{Quat, Rect, Vec2, Vec3};
+use bevy::prelude::{Component, EntityWorldMut, Reflect, TransformBundle};
+use bevy::transform::components::Transform;
+use tiled_parse::data_types::{
+    Object, ObjectType, Properties, TileAuxInfo, TileSet, TiledPropertyType,
+};
+
+use crate::error::TiledLoaderError;
+use crate::merge::{merge_rects, trace_outlines};
+#[cfg(feature = "rapier3d_colliders")]
+use crate::types::Plane3dSettings;
+use crate::types::{Serialized, TileColliders, TiledLoaderSettings};
+
+#[cfg(any(
+    all(feature = "rapier2d_colliders", feature = "avian2d_colliders"),
+    all(feature = "rapier2d_colliders", feature = "rapier3d_colliders"),
+    all(feature = "avian2d_colliders", feature = "rapier3d_colliders"),
+))]
+compile_error!(
+    "Only one of `rapier2d_colliders`, `avian2d_colliders` and `rapier3d_colliders` can be enabled."
+);
+#[cfg(not(any(
+    feature = "rapier2d_colliders",
+    feature = "avian2d_colliders",
+    feature = "rapier3d_colliders",
+)))]
+compile_error!(
+    "`colliders` needs `rapier2d_colliders`, `avian2d_colliders` or `rapier3d_colliders` to be enabled."
+);
+
+#[cfg(feature = "rapier2d_colliders")]
+use bevy_rapier2d as rapier;
+#[cfg(feature = "rapier3d_colliders")]
+use bevy_rapier3d as rapier;
+
+/// Shape of a collider, independent of the physics backend.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ColliderGeometry {
+    Ball {
+        radius: f32,
+    },
+    Cuboid {
+        half_extents: Vec2,
+    },
+    ConvexHull {
+        points: Vec<Vec2>,
+    },
+    /// Concave polygon, split into convex pieces by the backend.
+    ConvexDecomposition {
+        vertices: Vec<Vec2>,
+        indices: Vec<[u32; 2]>,
+    },
+    Polyline {
+        vertices: Vec<Vec2>,
+        indices: Option<Vec<[u32; 2]>>,
+    },
+    /// Unrotated shapes, at the given positions.
+    Compound {
+        shapes: Vec<(Vec2, ColliderGeometry)>,
+    },
+}
+
+#[cfg(feature = "rapier2d_colliders")]
+fn rapier_collider(
+    geometry: &ColliderGeometry,
+) -> Result<bevy_rapier2d::prelude::Collider, String> {
+    use bevy_rapier2d::prelude::Collider;
+
+    Ok(match geometry {
+        ColliderGeometry::Ball { radius } => Collider::ball(*radius),
+        ColliderGeometry::Cuboid { half_extents } => {
+            Collider::cuboid(half_extents.x, half_extents.y)
+        }
+        ColliderGeometry::ConvexHull { points } => {
+            Collider::convex_hull(points).ok_or("Shape has no convex hull")?
+        }
+        ColliderGeometry::ConvexDecomposition { vertices, indices } => {
+            Collider::convex_decomposition(vertices, indices)
+        }
+        ColliderGeometry::Polyline { vertices, indices } => {
+            Collider::polyline(vertices.clone(), indices.clone())
+        }
+        ColliderGeometry::Compound { shapes } => Collider::compound(
+            shapes
+                .iter()
+                .map(|(position, shape)| Ok((*position, 0., rapier_collider(shape)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+#[cfg(feature = "avian2d_colliders")]
+fn avian_collider(geometry: &ColliderGeometry) -> Result<avian2d::prelude::Collider, String> {
+    use avian2d::prelude::{Collider, Position, Rotation};
+
+    Ok(match geometry {
+        ColliderGeometry::Ball { radius } => Collider::circle(*radius),
+        ColliderGeometry::Cuboid { half_extents } => {
+            Collider::rectangle(2. * half_extents.x, 2. * half_extents.y)
+        }
+        ColliderGeometry::ConvexHull { points } => {
+            Collider::convex_hull(points.clone()).ok_or("Shape has no convex hull")?
+        }
+        ColliderGeometry::ConvexDecomposition { vertices, indices } => {
+            Collider::convex_decomposition(vertices.clone(), indices.clone())
+        }
+        ColliderGeometry::Polyline { vertices, indices } => {
+            Collider::polyline(vertices.clone(), indices.clone())
+        }
+        ColliderGeometry::Compound { shapes } => Collider::compound(
+            shapes
+                .iter()
+                .map(|(position, shape)| {
+                    Ok((
+                        Position(*position),
+                        Rotation::default(),
+                        avian_collider(shape)?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+#[cfg(feature = "rapier3d_colliders")]
+fn rapier3d_collider(
+    geometry: &ColliderGeometry,
+    height: f32,
+    segments: u32,
+) -> Result<bevy_rapier3d::prelude::Collider, String> {
+    use bevy_rapier3d::prelude::Collider;
+
+    let extruded = |points: &[Vec2]| {
+        points
+            .iter()
+            .flat_map(|p| [p.extend(0.), p.extend(height)])
+            .collect::<Vec<_>>()
+    };
+    let prism = |points: &[Vec2]| {
+        Collider::convex_hull(&extruded(points)).ok_or("Shape has no convex hull")
+    };
+    // NOTE:
+    // Edges become walls, the `2 * i`th vertex being the bottom of the `i`th point and the
+    // next one its top.
+    let walls = |points: &[Vec2], edges: &[[u32; 2]]| {
+        Collider::trimesh(
+            extruded(points),
+            edges
+                .iter()
+                .flat_map(|[a, b]| {
+                    let [a0, a1, b0, b1] = [2 * a, 2 * a + 1, 2 * b, 2 * b + 1];
+                    [[a0, b0, b1], [a0, b1, a1]]
+                })
+                .collect(),
+        )
+    };
+
+    Ok(match geometry {
+        ColliderGeometry::Ball { radius } => prism(
+            &(0..segments)
+                .map(|i| *radius * Vec2::from_angle(i as f32 / segments as f32 * TAU))
+                .collect::<Vec<_>>(),
+        )?,
+        ColliderGeometry::Cuboid { half_extents } => prism(&[
+            -*half_extents,
+            Vec2::new(half_extents.x, -half_extents.y),
+            *half_extents,
+            Vec2::new(-half_extents.x, half_extents.y),
+        ])?,
+        ColliderGeometry::ConvexHull { points } => prism(points)?,
+        // NOTE:
+        // Concave polygons only get their walls, as there is no 3d convex decomposition of a
+        // polygon.
+        ColliderGeometry::ConvexDecomposition { vertices, indices } => walls(vertices, indices),
+        ColliderGeometry::Polyline { vertices, indices } => match indices {
+            Some(indices) => walls(vertices, indices),
+            None => walls(
+                vertices,
+                &(1..vertices.len() as u32)
+                    .map(|i| [i - 1, i])
+                    .collect::<Vec<_>>(),
+            ),
+        },
+        ColliderGeometry::Compound { shapes } => Collider::compound(
+            shapes
+                .iter()
+                .map(|(position, shape)| {
+                    Ok((
+                        position.extend(0.),
+                        Quat::IDENTITY,
+                        rapier3d_collider(shape, height, segments)?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+/// Builds the collider of the enabled backend, ready to be stored in the scene.
+pub(crate) fn serialize_collider(
+    geometry: &ColliderGeometry,
+    #[cfg_attr(not(feature = "rapier3d_colliders"), allow(unused_variables))]
+    settings: &TiledLoaderSettings,
+) -> Result<Serialized, String> {
+    #[cfg(feature = "rapier2d_colliders")]
+    let collider = rapier_collider(geometry)?;
+    #[cfg(feature = "avian2d_colliders")]
+    let collider = avian_collider(geometry)?;
+    #[cfg(feature = "rapier3d_colliders")]
+    let collider = rapier3d_collider(
+        geometry,
+        settings.plane3d().map_or_else(
+            || Plane3dSettings::default().collider_height,
+            |p| p.collider_height,
+        ),
+        settings.ellipse_segments,
+    )?;
+
+    Serialized::new(&collider).map_err(|e| e.to_string())
+}
+
+/// Removes the collider of an entity, whether it was deserialized yet or not.
+pub(crate) fn remove_collider(entity: &mut EntityWorldMut) {
+    entity.remove::<Serialized>();
+    #[cfg(any(feature = "rapier2d_colliders", feature = "rapier3d_colliders"))]
+    entity.remove::<rapier::prelude::Collider>();
+    #[cfg(feature = "avian2d_colliders")]
+    entity.remove::<avian2d::prelude::Collider>();
+}
+
+/// Collision rectangles of a tile that are part of its layer's merged collider, so that the
+/// collider can be rebuilt when tiles are edited.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+pub struct MergedTileRects(pub Vec<Rect>);
+
+/// Where the collision shapes of a tile end up, relative to the entity they are added to.
+#[derive(Clone, Copy)]
+pub(crate) struct ShapesPlacement {
+    /// Position of the tile's top-left corner.
+    pub origin: Vec2,
+    /// How much the tile is stretched.
+    pub scale: Vec2,
+    /// Size of the tile in its tileset, before `scale`.
+    pub tile_size: Vec2,
+    /// Mirroring of the tile, in Tiled's `+ y` down space. Rotations are left to the entity.
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+/// Spawns the collision shapes of a tile, placed on the layer named `layer`, as children of `e`.
+/// Physics properties missing from a shape are taken from `defaults`.
+pub(crate) fn add_colliders<'a>(
+    e: &mut EntityWorldMut,
+    os: impl IntoIterator<Item = &'a Object>,
+    layer: &str,
+    defaults: ColliderProperties,
+    ShapesPlacement {
+        origin,
+        scale,
+        tile_size,
+        flip_x,
+        flip_y,
+    }: ShapesPlacement,
+    settings: &TiledLoaderSettings,
+) -> Result<(), TiledLoaderError> {
+    let y_sign = settings.y_sign();
+    let mirror = Vec2::new(if flip_x { -1. } else { 1. }, if flip_y { -1. } else { 1. });
+
+    let mut result = Ok(());
+
+    e.with_children(|cb| {
+        let filter = &settings.collider_filter;
+
+        result = os
+            .into_iter()
+            .filter(|o| filter.matches(o, layer))
+            .try_for_each(
+                |object @ Object {
+                     position: (x, y),
+                     rotation,
+                     ..
+                 }| {
+                    let Some((
+                        Vec2 {
+                            x: offset_x,
+                            y: offset_y,
+                        },
+                        collider,
+                    )) = shape_collider(object, scale, mirror, defaults, settings)?
+                    else {
+                        return Ok(());
+                    };
+
+                    // NOTE:
+                    // Mirroring the tile mirrors the object's position within it, and reverses
+                    // the direction of its rotation.
+                    let position = Vec2::new(
+                        if flip_x { tile_size.x - *x } else { *x },
+                        if flip_y { tile_size.y - *y } else { *y },
+                    );
+                    let rotation = (mirror.x * mirror.y * rotation).to_radians();
+
+                    // NOTE:
+                    // Tiled rotates shapes around their position, not their center.
+                    let center = scale * position
+                        + Vec2::from_angle(rotation).rotate(Vec2::new(offset_x, offset_y));
+
+                    cb.spawn((
+                        TransformBundle::from_transform(
+                            Transform::from_xyz(
+                                origin.x + center.x,
+                                origin.y + y_sign * center.y,
+                                0.,
+                            )
+                            .with_rotation(Quat::from_axis_angle(
+                                Vec3::Z,
+                                // Tiled rotates clockwise in a `+ y` down space.
+                                y_sign * rotation,
+                            )),
+                        ),
+                        collider,
+                    ));
+
+                    Ok(())
+                },
+            )
+    });
+
+    result
+}
+
+/// Gives an object of an object layer its collider, as a child of its entity `e`.
+pub(crate) fn add_object_collider(
+    e: &mut EntityWorldMut,
+    object: &Object,
+    settings: &TiledLoaderSettings,
+) -> Result<(), TiledLoaderError> {
+    let y_sign = settings.y_sign();
+
+    let Some((offset, collider)) = shape_collider(
+        object,
+        Vec2::ONE,
+        Vec2::ONE,
+        ColliderProperties::default(),
+        settings,
+    )?
+    else {
+        return Ok(());
+    };
+
+    // NOTE:
+    // The object's entity is already at its position, with its rotation.
+    e.with_children(|cb| {
+        cb.spawn((
+            TransformBundle::from_transform(Transform::from_xyz(offset.x, y_sign * offset.y, 0.)),
+            collider,
+        ));
+    });
+
+    Ok(())
+}
+
+/// Builds the collider of a collision shape, stretched by `scale` and mirrored by `mirror`.
+///
+/// Returns the offset of its center from the shape's position (in Tiled's `+ y` down space,
+/// before the shape's rotation), and the collider along with the components of its physics
+/// properties.
+fn shape_collider(
+    Object {
+        id,
+        size,
+        otype,
+        properties,
+        ..
+    }: &Object,
+    scale: Vec2,
+    mirror: Vec2,
+    defaults: ColliderProperties,
+    settings: &TiledLoaderSettings,
+) -> Result<Option<(Vec2, Serialized)>, TiledLoaderError> {
+    let collider_error = |reason| TiledLoaderError::Collider {
+        object_id: *id,
+        reason,
+    };
+
+    let polygon = match properties.get("collider_shape") {
+        Some(TiledPropertyType::String(s)) => s
+            .parse()
+            .map_err(|_| collider_error(format!("Unknown collider_shape `{s}`")))?,
+        _ => PolygonCollider::default(),
+    };
+    let physics = ColliderProperties::read(properties)
+        .map_err(collider_error)?
+        .or(defaults);
+
+    let Some((offset, geometry)) = construct_geometry(
+        otype,
+        size.map(|(x, y)| Vec2 { x, y }),
+        Some(scale),
+        settings.y_sign(),
+        mirror,
+        settings.ellipse_segments,
+        polygon,
+    )
+    .map_err(collider_error)?
+    else {
+        return Ok(None);
+    };
+
+    let mut collider = serialize_collider(&geometry, settings).map_err(collider_error)?;
+    physics
+        .push(&mut collider)
+        .map_err(|e| collider_error(e.to_string()))?;
+
+    Ok(Some((offset, collider)))
+}
+
+/// How a polygon object becomes a collider, picked with its `collider_shape` property.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum PolygonCollider {
+    /// Concave polygons are split into a compound of convex pieces.
+    #[default]
+    Decomposition,
+    /// The polygon is filled up to its convex hull.
+    ConvexHull,
+    /// Only the outline of the polygon collides.
+    Polyline,
+}
+
+impl FromStr for PolygonCollider {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decomposition" => Ok(PolygonCollider::Decomposition),
+            "convex_hull" => Ok(PolygonCollider::ConvexHull),
+            "polyline" => Ok(PolygonCollider::Polyline),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Physics properties of a collision shape, read from its Tiled properties.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ColliderProperties {
+    /// `sensor` : the collider detects intersections without blocking anything.
+    pub sensor: Option<bool>,
+    /// `friction`
+    pub friction: Option<f32>,
+    /// `restitution`
+    pub restitution: Option<f32>,
+    /// `collision_groups` (the groups the collider is part of) and `collision_filter` (the
+    /// groups it interacts with), as bit masks. A missing one means all groups.
+    pub collision_groups: Option<(u32, u32)>,
+    /// `solver_groups` and `solver_filter`, like `collision_groups`, for the contact forces.
+    /// Not supported by avian.
+    pub solver_groups: Option<(u32, u32)>,
+}
+
+impl ColliderProperties {
+    pub(crate) fn read(properties: &Properties) -> Result<Self, String> {
+        let groups = |memberships, filters| -> Result<_, String> {
+            let memberships = typed_property(properties, memberships, "an int", int)?;
+            let filters = typed_property(properties, filters, "an int", int)?;
+
+            Ok((memberships.is_some() || filters.is_some())
+                .then(|| (memberships.unwrap_or(u32::MAX), filters.unwrap_or(u32::MAX))))
+        };
+
+        Ok(Self {
+            sensor: typed_property(properties, "sensor", "a bool", |p| match p {
+                TiledPropertyType::Bool(v) => Some(*v),
+                _ => None,
+            })?,
+            friction: typed_property(properties, "friction", "a number", float)?,
+            restitution: typed_property(properties, "restitution", "a number", float)?,
+            collision_groups: groups("collision_groups", "collision_filter")?,
+            solver_groups: groups("solver_groups", "solver_filter")?,
+        })
+    }
+
+    /// Takes the properties missing from `self` from `fallback`.
+    pub(crate) fn or(self, fallback: Self) -> Self {
+        Self {
+            sensor: self.sensor.or(fallback.sensor),
+            friction: self.friction.or(fallback.friction),
+            restitution: self.restitution.or(fallback.restitution),
+            collision_groups: self.collision_groups.or(fallback.collision_groups),
+            solver_groups: self.solver_groups.or(fallback.solver_groups),
+        }
+    }
+
+    /// Stores the components of the properties next to the collider they apply to.
+    #[cfg(any(feature = "rapier2d_colliders", feature = "rapier3d_colliders"))]
+    fn push(&self, collider: &mut Serialized) -> bincode::Result<()> {
+        use rapier::prelude::*;
+
+        if self.sensor == Some(true) {
+            collider.push(&Sensor)?;
+        }
+        if let Some(friction) = self.friction {
+            collider.push(&Friction::coefficient(friction))?;
+        }
+        if let Some(restitution) = self.restitution {
+            collider.push(&Restitution::coefficient(restitution))?;
+        }
+        if let Some((memberships, filters)) = self.collision_groups {
+            collider.push(&CollisionGroups::new(
+                Group::from_bits_truncate(memberships),
+                Group::from_bits_truncate(filters),
+            ))?;
+        }
+        if let Some((memberships, filters)) = self.solver_groups {
+            collider.push(&SolverGroups::new(
+                Group::from_bits_truncate(memberships),
+                Group::from_bits_truncate(filters),
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Stores the components of the properties next to the collider they apply to.
+    #[cfg(feature = "avian2d_colliders")]
+    fn push(&self, collider: &mut Serialized) -> bincode::Result<()> {
+        use avian2d::prelude::*;
+
+        if self.sensor == Some(true) {
+            collider.push(&Sensor)?;
+        }
+        if let Some(friction) = self.friction {
+            collider.push(&Friction::new(friction))?;
+        }
+        if let Some(restitution) = self.restitution {
+            collider.push(&Restitution::new(restitution))?;
+        }
+        if let Some((memberships, filters)) = self.collision_groups {
+            collider.push(&CollisionLayers::from_bits(memberships, filters))?;
+        }
+        Ok(())
+    }
+}
+
+/// Rigid body of a tile, and the physics properties its collision shapes default to.
+pub(crate) fn tile_physics(
+    tileset: &TileSet,
+    tile: u32,
+    tile_aux_info: &TileAuxInfo,
+) -> Result<(BodyProperties, ColliderProperties), TiledLoaderError> {
+    let error = |reason| TiledLoaderError::TileProperties {
+        tileset: tileset.name.clone(),
+        tile,
+        reason,
+    };
+
+    Ok((
+        BodyProperties::read(&tile_aux_info.properties).map_err(error)?,
+        ColliderProperties::read(&tile_aux_info.properties).map_err(error)?,
+    ))
+}
+
+/// Kind of rigid body, from the `body` property.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BodyKind {
+    Static,
+    Dynamic,
+    Kinematic,
+}
+
+impl FromStr for BodyKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "static" => Ok(BodyKind::Static),
+            "dynamic" => Ok(BodyKind::Dynamic),
+            "kinematic" => Ok(BodyKind::Kinematic),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Rigid body of an object or tile, read from its Tiled properties. Its colliders are the ones
+/// spawned as its children.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct BodyProperties {
+    /// `body` : `static`, `dynamic` or `kinematic`.
+    pub body: Option<BodyKind>,
+    /// `mass` : added to the mass the colliders get from their density.
+    pub mass: Option<f32>,
+}
+
+impl BodyProperties {
+    pub(crate) fn read(properties: &Properties) -> Result<Self, String> {
+        Ok(Self {
+            body: typed_property(
+                properties,
+                "body",
+                "`static`, `dynamic` or `kinematic`",
+                |p| match p {
+                    TiledPropertyType::String(s) => s.parse().ok(),
+                    _ => None,
+                },
+            )?,
+            mass: typed_property(properties, "mass", "a number", float)?,
+        })
+    }
+
+    /// Takes the properties missing from `self` from `fallback`.
+    pub(crate) fn or(self, fallback: Self) -> Self {
+        Self {
+            body: self.body.or(fallback.body),
+            mass: self.mass.or(fallback.mass),
+        }
+    }
+
+    /// Gives the rigid body to `e`, as [`Serialized`] components like its colliders.
+    pub(crate) fn insert(&self, e: &mut EntityWorldMut) -> Result<(), String> {
+        let mut serialized = e.take::<Serialized>().unwrap_or_default();
+        self.push(&mut serialized).map_err(|err| err.to_string())?;
+
+        if !serialized.components.is_empty() {
+            e.insert(serialized);
+        }
+        Ok(())
+    }
+
+    #[cfg(any(feature = "rapier2d_colliders", feature = "rapier3d_colliders"))]
+    fn push(&self, serialized: &mut Serialized) -> bincode::Result<()> {
+        use rapier::prelude::*;
+
+        if let Some(body) = self.body {
+            serialized.push(&match body {
+                BodyKind::Static => RigidBody::Fixed,
+                BodyKind::Dynamic => RigidBody::Dynamic,
+                BodyKind::Kinematic => RigidBody::KinematicPositionBased,
+            })?;
+        }
+        if let Some(mass) = self.mass {
+            serialized.push(&AdditionalMassProperties::Mass(mass))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "avian2d_colliders")]
+    fn push(&self, serialized: &mut Serialized) -> bincode::Result<()> {
+        use avian2d::prelude::*;
+
+        if let Some(body) = self.body {
+            serialized.push(&match body {
+                BodyKind::Static => RigidBody::Static,
+                BodyKind::Dynamic => RigidBody::Dynamic,
+                BodyKind::Kinematic => RigidBody::Kinematic,
+            })?;
+        }
+        if let Some(mass) = self.mass {
+            serialized.push(&Mass(mass))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the property `name`, if there is one, erroring when `f` can't make sense of it.
+fn typed_property<T>(
+    properties: &Properties,
+    name: &str,
+    kind: &str,
+    f: impl FnOnce(&TiledPropertyType) -> Option<T>,
+) -> Result<Option<T>, String> {
+    properties
+        .get(name)
+        .map(|p| f(p).ok_or_else(|| format!("Property `{name}` should be {kind}")))
+        .transpose()
+}
+
+fn int(p: &TiledPropertyType) -> Option<u32> {
+    match p {
+        // NOTE:
+        // Bit masks, so `-1` stands for all bits.
+        TiledPropertyType::Int(v) => Some(*v as u32),
+        _ => None,
+    }
+}
+
+fn float(p: &TiledPropertyType) -> Option<f32> {
+    match p {
+        TiledPropertyType::Float(v) => Some(*v),
+        TiledPropertyType::Int(v) => Some(*v as f32),
+        _ => None,
+    }
+}
+
+fn construct_geometry(
+    shape: &ObjectType,
+    size: Option<Vec2>,
+    scale_factor: Option<Vec2>,
+    y_sign: f32,
+    mirror: Vec2,
+    ellipse_segments: u32,
+    polygon: PolygonCollider,
+) -> Result<Option<(Vec2, ColliderGeometry)>, String> {
+    // NOTE:
+    // Mirroring is folded into the scale : shapes are built from their position, so mirroring
+    // their extents mirrors the shape.
+    let scale_factor = scale_factor.unwrap_or(Vec2::ONE) * mirror;
+
+    Ok(Some(match shape {
+        ObjectType::Rectangle => {
+            let Some(size) = size else {
+                return Err("Rectangle has no size".into());
+            };
+            (
+                size / 2. * scale_factor,
+                ColliderGeometry::Cuboid {
+                    half_extents: (size / 2. * scale_factor).abs(),
+                },
+            )
+        }
+        ObjectType::Ellipse => {
+            let Some(size) = size else {
+                return Err("Ellipse has no size".into());
+            };
+            let radii = (size / 2. * scale_factor).abs();
+
+            (
+                size / 2. * scale_factor,
+                if radii.x == radii.y {
+                    ColliderGeometry::Ball { radius: radii.x }
+                } else {
+                    // NOTE:
+                    // Approximated by a polygon whose vertices lie on the ellipse.
+                    ColliderGeometry::ConvexHull {
+                        points: (0..ellipse_segments)
+                            .map(|i| {
+                                let angle = i as f32 / ellipse_segments as f32 * TAU;
+                                radii * Vec2::from_angle(angle)
+                            })
+                            .collect(),
+                    }
+                },
+            )
+        }
+        ObjectType::Polygon(points) => {
+            let vertices = points
+                .iter()
+                .map(|(x, y)| Vec2::new(scale_factor.x * *x, scale_factor.y * y_sign * *y))
+                .collect::<Vec<_>>();
+
+            if vertices.len() < 3 {
+                return Err("Polygon has less than 3 points".into());
+            }
+            if polygon_area(&vertices).abs() <= f32::EPSILON {
+                return Err("Polygon has no area".into());
+            }
+
+            let outline = (0..vertices.len() as u32)
+                .map(|i| [i, (i + 1) % vertices.len() as u32])
+                .collect::<Vec<_>>();
+
+            (
+                Vec2::ZERO,
+                match polygon {
+                    PolygonCollider::Decomposition if !is_convex(&vertices) => {
+                        ColliderGeometry::ConvexDecomposition {
+                            vertices,
+                            indices: outline,
+                        }
+                    }
+                    PolygonCollider::Decomposition | PolygonCollider::ConvexHull => {
+                        ColliderGeometry::ConvexHull { points: vertices }
+                    }
+                    PolygonCollider::Polyline => ColliderGeometry::Polyline {
+                        vertices,
+                        indices: Some(outline),
+                    },
+                },
+            )
+        }
+        ObjectType::Polyline(points) => (
+            Vec2::ZERO,
+            ColliderGeometry::Polyline {
+                vertices: points
+                    .iter()
+                    .map(|p| Vec2 {
+                        x: scale_factor.x * p.0,
+                        y: scale_factor.y * y_sign * p.1,
+                    })
+                    .collect(),
+                indices: None,
+            },
+        ),
+        // NOTE:
+        // Points have no area to collide with.
+        ObjectType::Point => return Ok(None),
+    }))
+}
+
+/// Signed area of a polygon, positive when its vertices wind counter-clockwise.
+fn polygon_area(vertices: &[Vec2]) -> f32 {
+    vertices
+        .iter()
+        .zip(vertices.iter().cycle().skip(1))
+        .map(|(a, b)| a.perp_dot(*b))
+        .sum::<f32>()
+        / 2.
+}
+
+/// Whether every turn along the polygon goes the same way.
+fn is_convex(vertices: &[Vec2]) -> bool {
+    let turns = (0..vertices.len()).map(|i| {
+        let [a, b, c] = [0, 1, 2].map(|o| vertices[(i + o) % vertices.len()]);
+        (b - a).perp_dot(c - b)
+    });
+
+    turns.clone().all(|t| t >= 0.) || turns.into_iter().all(|t| t <= 0.)
+}
+
+/// Area covered by an unrotated rectangle collision shape of a tile, in the layer's `+ y` down
+/// space. Other shapes have none.
+pub(crate) fn tile_rect(
+    object: &Object,
+    cell_center: Vec2,
+    tile_size: Vec2,
+    flip_x: bool,
+    flip_y: bool,
+    quarter_turn: bool,
+) -> Option<Rect> {
+    let (ObjectType::Rectangle, Some((w, h))) = (&object.otype, object.size) else {
+        return None;
+    };
+    let size = Vec2::new(w, h);
+
+    let mut min = Vec2::new(object.position.0, object.position.1);
+    if flip_x {
+        min.x = tile_size.x - min.x - size.x;
+    }
+    if flip_y {
+        min.y = tile_size.y - min.y - size.y;
+    }
+
+    let [a, b] = [min, min + size].map(|p| {
+        let p = p - tile_size / 2.;
+        // Clockwise, in a `+ y` down space.
+        if quarter_turn {
+            Vec2::new(-p.y, p.x)
+        } else {
+            p
+        }
+    });
+
+    Some(Rect::from_corners(cell_center + a, cell_center + b))
+}
+
+/// Single collider covering the tile rectangles of a layer, according to
+/// [`TiledLoaderSettings::tile_colliders`].
+pub(crate) fn merged_collider(rects: &[Rect], settings: &TiledLoaderSettings) -> ColliderGeometry {
+    let y_sign = settings.y_sign();
+
+    match settings.tile_colliders {
+        TileColliders::Outline => {
+            let mut vertices = Vec::new();
+            let mut indices = Vec::new();
+
+            for outline in trace_outlines(rects) {
+                let (first, n) = (vertices.len() as u32, outline.len() as u32);
+                indices.extend((0..n).map(|i| [first + i, first + (i + 1) % n]));
+                vertices.extend(outline.into_iter().map(|p| Vec2::new(p.x, y_sign * p.y)));
+            }
+
+            ColliderGeometry::Polyline {
+                vertices,
+                indices: Some(indices),
+            }
+        }
+        TileColliders::Merged => ColliderGeometry::Compound {
+            shapes: merge_rects(rects)
+                .into_iter()
+                .map(|r| {
+                    (
+                        Vec2::new(r.center().x, y_sign * r.center().y),
+                        ColliderGeometry::Cuboid {
+                            half_extents: r.half_size(),
+                        },
+                    )
+                })
+                .collect(),
+        },
+        TileColliders::PerTile => {
+            unreachable!("layers with per tile colliders don't collect rectangles to merge")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiled_parse::data_types::PairF32;
+
+    use crate::test_util::{object, properties};
+
+    fn geometry(otype: ObjectType, size: Vec2) -> (Vec2, ColliderGeometry) {
+        construct_geometry(
+            &otype,
+            Some(size),
+            None,
+            -1.,
+            Vec2::ONE,
+            32,
+            PolygonCollider::default(),
+        )
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn circle_collider_is_a_ball() {
+        let (offset, geometry) = geometry(ObjectType::Ellipse, Vec2::new(16., 16.));
+
+        assert_eq!(offset, Vec2::new(8., 8.));
+        assert_eq!(geometry, ColliderGeometry::Ball { radius: 8. });
+    }
+
+    #[test]
+    fn ellipse_collider_matches_object_size() {
+        let (offset, geometry) = geometry(ObjectType::Ellipse, Vec2::new(40., 20.));
+        let ColliderGeometry::ConvexHull { points } = geometry else {
+            panic!("Ellipse should be a convex hull, got {geometry:?}");
+        };
+        let half_extents = points.iter().fold(Vec2::ZERO, |e, p| e.max(p.abs()));
+
+        assert_eq!(offset, Vec2::new(20., 10.));
+        assert!((half_extents.x - 20.).abs() < 1e-4);
+        assert!((half_extents.y - 10.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn point_has_no_collider() {
+        assert!(construct_geometry(
+            &ObjectType::Point,
+            None,
+            None,
+            -1.,
+            Vec2::ONE,
+            32,
+            PolygonCollider::default()
+        )
+        .unwrap()
+        .is_none());
+    }
+
+    fn polygon(
+        points: &[PairF32],
+        mode: PolygonCollider,
+    ) -> Result<Option<(Vec2, ColliderGeometry)>, String> {
+        construct_geometry(
+            &ObjectType::Polygon(points.to_vec()),
+            None,
+            None,
+            1.,
+            Vec2::ONE,
+            32,
+            mode,
+        )
+    }
+
+    const L_SHAPE: [PairF32; 6] = [
+        (0., 0.),
+        (32., 0.),
+        (32., 8.),
+        (8., 8.),
+        (8., 32.),
+        (0., 32.),
+    ];
+
+    #[test]
+    fn concave_polygon_is_decomposed() {
+        let (_, geometry) = polygon(&L_SHAPE, PolygonCollider::Decomposition)
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(
+            geometry,
+            ColliderGeometry::ConvexDecomposition { .. }
+        ));
+    }
+
+    #[test]
+    fn degenerate_polygon_is_an_error() {
+        assert!(polygon(&[(0., 0.), (8., 0.)], PolygonCollider::Decomposition).is_err());
+        assert!(polygon(
+            &[(0., 0.), (8., 0.), (16., 0.)],
+            PolygonCollider::Decomposition
+        )
+        .is_err());
+    }
+
+    fn shape(class: &str, props: &[(&str, TiledPropertyType)]) -> Object {
+        Object {
+            properties: properties(props),
+            ..object(class, "")
+        }
+    }
+
+    #[test]
+    fn collider_filters() {
+        use crate::types::ColliderFilter;
+
+        let flagged = shape("", &[("collider", TiledPropertyType::Bool(true))]);
+        let solid = shape("solid", &[]);
+
+        assert!(ColliderFilter::default().matches(&flagged, "Ground"));
+        assert!(!ColliderFilter::default().matches(&solid, "Ground"));
+        assert!(ColliderFilter::Class("solid".into()).matches(&solid, "Ground"));
+        assert!(ColliderFilter::LayerName("Ground".into()).matches(&solid, "Ground"));
+        assert!(!ColliderFilter::LayerName("Ground".into()).matches(&solid, "Decor"));
+        assert!(ColliderFilter::Any(vec![
+            ColliderFilter::default(),
+            ColliderFilter::Class("solid".into())
+        ])
+        .matches(&solid, "Decor"));
+    }
+
+    #[test]
+    fn collider_properties() {
+        let object = shape(
+            "",
+            &[
+                ("sensor", TiledPropertyType::Bool(true)),
+                ("friction", TiledPropertyType::Float(0.5)),
+                ("restitution", TiledPropertyType::Int(1)),
+                ("collision_groups", TiledPropertyType::Int(0b10)),
+            ],
+        );
+
+        assert_eq!(
+            ColliderProperties::read(&object.properties),
+            Ok(ColliderProperties {
+                sensor: Some(true),
+                friction: Some(0.5),
+                restitution: Some(1.),
+                collision_groups: Some((0b10, u32::MAX)),
+                solver_groups: None,
+            })
+        );
+        assert!(ColliderProperties::read(
+            &shape("", &[("sensor", TiledPropertyType::Int(1))]).properties
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn body_properties_fall_back_to_the_tile() {
+        let tile = shape(
+            "",
+            &[
+                ("body", TiledPropertyType::String("dynamic".into())),
+                ("mass", TiledPropertyType::Float(2.)),
+            ],
+        );
+        let object = shape("", &[("mass", TiledPropertyType::Int(5))]);
+
+        assert_eq!(
+            BodyProperties::read(&object.properties)
+                .unwrap()
+                .or(BodyProperties::read(&tile.properties).unwrap()),
+            BodyProperties {
+                body: Some(BodyKind::Dynamic),
+                mass: Some(5.),
+            }
+        );
+        assert!(BodyProperties::read(
+            &shape(
+                "",
+                &[("body", TiledPropertyType::String("floating".into()))]
+            )
+            .properties
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "rapier2d_colliders")]
+    #[test]
+    fn rapier_decomposition_keeps_concavity() {
+        let (_, geometry) = polygon(&L_SHAPE, PolygonCollider::Decomposition)
+            .unwrap()
+            .unwrap();
+        let collider = rapier_collider(&geometry).unwrap();
+
+        assert!(collider.as_compound().is_some());
+        assert!(collider.contains_local_point(Vec2::new(4., 20.)));
+        assert!(collider.contains_local_point(Vec2::new(20., 4.)));
+        assert!(!collider.contains_local_point(Vec2::new(20., 20.)));
+    }
+
+    #[cfg(feature = "rapier2d_colliders")]
+    #[test]
+    fn rapier_convex_hull_fills_concave_polygon() {
+        let (_, geometry) = polygon(&L_SHAPE, PolygonCollider::ConvexHull)
+            .unwrap()
+            .unwrap();
+        let collider = rapier_collider(&geometry).unwrap();
+
+        assert!(collider.contains_local_point(Vec2::new(16., 16.)));
+    }
+
+    #[cfg(feature = "avian2d_colliders")]
+    #[test]
+    fn avian_decomposition_keeps_concavity() {
+        let (_, geometry) = polygon(&L_SHAPE, PolygonCollider::Decomposition)
+            .unwrap()
+            .unwrap();
+        let collider = avian_collider(&geometry).unwrap();
+        let contains = |p: Vec2| {
+            collider.contains_point(
+                avian2d::prelude::Position::default(),
+                avian2d::prelude::Rotation::default(),
+                p,
+            )
+        };
+
+        assert!(contains(Vec2::new(4., 20.)));
+        assert!(contains(Vec2::new(20., 4.)));
+        assert!(!contains(Vec2::new(20., 20.)));
+    }
+}
@@ -0,0 +1,279 @@
+//! Tile layers drawn by `bevy_ecs_tilemap`, for [`TileRenderMode::EcsTilemap`].
+//!
+//! The map scene only holds [`TileChunk`]s, which become tilemaps once the scene is spawned, so
+//! that none of `bevy_ecs_tilemap`'s components need to be stored in it.
+//!
+//! [`TileRenderMode::EcsTilemap`]: crate::types::TileRenderMode::EcsTilemap
+
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::{
+    TileBundle, TileColor, TileFlip, TilePos as EcsTilePos, TileStorage as EcsTileStorage,
+    TileTextureIndex, TilemapBundle, TilemapGridSize, TilemapId, TilemapSize, TilemapSpacing,
+    TilemapTexture, TilemapTileSize, TilemapType,
+};
+
+use crate::load::MapSpawnContext;
+use crate::types::{ChunkTile, TileChunk, TilePos};
+
+/// Marks a [`TileChunk`] drawn as a tilemap, with what the tilemap needs besides the chunk.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+pub struct EcsTilemapChunk {
+    pub texture: Handle<Image>,
+    pub spacing: Vec2,
+}
+
+pub(crate) fn ecs_tilemap_chunk(tileset_index: usize, ctx: &MapSpawnContext) -> EcsTilemapChunk {
+    EcsTilemapChunk {
+        texture: ctx.tilemap_textures[tileset_index].clone(),
+        spacing: Vec2::splat(ctx.tile_sets[tileset_index].spacing as f32),
+    }
+}
+
+/// Turns [`EcsTilemapChunk`]s into tilemaps when they are spawned, and updates their tiles when
+/// they change.
+pub(crate) fn sync_ecs_tilemaps(
+    mut chunks: Query<
+        (
+            Entity,
+            &TileChunk,
+            &EcsTilemapChunk,
+            Option<&mut EcsTileStorage>,
+        ),
+        Changed<TileChunk>,
+    >,
+    tiles: Query<(&TileTextureIndex, &TileFlip, &TileColor)>,
+    mut commands: Commands,
+) {
+    chunks
+        .iter_mut()
+        .for_each(|(tilemap, chunk, ecs_chunk, storage)| {
+            let size = TilemapSize {
+                x: chunk.size.x,
+                y: chunk.size.y,
+            };
+            let color = TileColor(Color::WHITE.with_alpha(chunk.opacity));
+
+            // NOTE:
+            // `bevy_ecs_tilemap` counts rows from the bottom.
+            let positions = (0..size.y).flat_map(|y| {
+                (0..size.x).map(move |x| {
+                    (
+                        TilePos::new(chunk.origin.x + x, chunk.origin.y + y),
+                        EcsTilePos::new(x, size.y - 1 - y),
+                    )
+                })
+            });
+
+            let tile_bundle = |position, tile: ChunkTile| TileBundle {
+                position,
+                tilemap_id: TilemapId(tilemap),
+                texture_index: TileTextureIndex(tile.index),
+                flip: TileFlip {
+                    x: tile.flip_h,
+                    y: tile.flip_v,
+                    d: tile.flip_d,
+                },
+                color,
+                ..Default::default()
+            };
+
+            let Some(mut storage) = storage else {
+                let mut storage = EcsTileStorage::empty(size);
+
+                positions.for_each(|(pos, ecs_pos)| {
+                    if let Some(tile) = chunk.get(pos) {
+                        let tile_ent = commands.spawn(tile_bundle(ecs_pos, tile)).id();
+                        commands.entity(tilemap).add_child(tile_ent);
+                        storage.set(&ecs_pos, tile_ent);
+                    }
+                });
+
+                commands
+                    .entity(tilemap)
+                    .insert(tilemap_bundle(chunk, ecs_chunk, size, storage));
+                return;
+            };
+
+            positions.for_each(
+                |(pos, ecs_pos)| match (chunk.get(pos), storage.get(&ecs_pos)) {
+                    (Some(tile), Some(tile_ent)) => {
+                        let bundle = tile_bundle(ecs_pos, tile);
+                        let unchanged = tiles.get(tile_ent).is_ok_and(|(index, flip, c)| {
+                            *index == bundle.texture_index && *flip == bundle.flip && *c == color
+                        });
+                        if !unchanged {
+                            commands.entity(tile_ent).insert((
+                                bundle.texture_index,
+                                bundle.flip,
+                                color,
+                            ));
+                        }
+                    }
+                    (Some(tile), None) => {
+                        let tile_ent = commands.spawn(tile_bundle(ecs_pos, tile)).id();
+                        commands.entity(tilemap).add_child(tile_ent);
+                        storage.set(&ecs_pos, tile_ent);
+                    }
+                    (None, Some(tile_ent)) => {
+                        commands.entity(tile_ent).despawn_recursive();
+                        storage.remove(&ecs_pos);
+                    }
+                    (None, None) => {}
+                },
+            );
+        });
+}
+
+/// Places the tilemap so that its tiles are where their sprites would be.
+fn tilemap_bundle(
+    chunk: &TileChunk,
+    ecs_chunk: &EcsTilemapChunk,
+    size: TilemapSize,
+    storage: EcsTileStorage,
+) -> TilemapBundle {
+    // NOTE:
+    // Tiles are centered on their grid position, and drawn from the bottom-left corner of their
+    // cell in Tiled. With `+ y` down the tilemap is mirrored, like sprites are flipped.
+    let top_row = (chunk.origin.y + size.y) as f32 * chunk.cell_size.y;
+    let translation = Vec2::new(
+        chunk.origin.x as f32 * chunk.cell_size.x + chunk.tile_size.x / 2.,
+        chunk.y_sign * (top_row - chunk.tile_size.y / 2.),
+    );
+
+    TilemapBundle {
+        grid_size: TilemapGridSize {
+            x: chunk.cell_size.x,
+            y: chunk.cell_size.y,
+        },
+        map_type: TilemapType::Square,
+        size,
+        spacing: TilemapSpacing {
+            x: ecs_chunk.spacing.x,
+            y: ecs_chunk.spacing.y,
+        },
+        storage,
+        texture: TilemapTexture::Single(ecs_chunk.texture.clone()),
+        tile_size: TilemapTileSize {
+            x: chunk.tile_size.x,
+            y: chunk.tile_size.y,
+        },
+        transform: Transform::from_translation(translation.extend(0.)).with_scale(Vec3::new(
+            1.,
+            -chunk.y_sign,
+            1.,
+        )),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn chunk_tile(index: u32, flip_h: bool, flip_d: bool) -> ChunkTile {
+        ChunkTile {
+            index,
+            flip_h,
+            flip_v: false,
+            flip_d,
+            elevation: 0,
+            side_index: index,
+        }
+    }
+
+    #[test]
+    fn tilemaps_follow_their_chunk() {
+        let mut world = World::new();
+        let tilemap = world
+            .spawn((
+                TileChunk {
+                    origin: TilePos::new(2, 2),
+                    size: UVec2::new(2, 2),
+                    tiles: vec![
+                        Some(chunk_tile(1, true, false)),
+                        None,
+                        Some(chunk_tile(3, false, false)),
+                        None,
+                    ],
+                    layout: Handle::default(),
+                    cell_size: Vec2::splat(16.),
+                    tile_size: Vec2::new(16., 32.),
+                    y_sign: 1.,
+                    opacity: 1.,
+                    step_height: 0.,
+                },
+                EcsTilemapChunk::default(),
+            ))
+            .id();
+        world.run_system_once(sync_ecs_tilemaps);
+
+        let tile_at = |world: &World, x, y| {
+            world
+                .get::<EcsTileStorage>(tilemap)
+                .unwrap()
+                .get(&EcsTilePos::new(x, y))
+        };
+        let tile = |world: &World, e| {
+            (
+                world.get::<TileTextureIndex>(e).unwrap().0,
+                *world.get::<TileFlip>(e).unwrap(),
+                world.get::<Parent>(e).map(Parent::get),
+            )
+        };
+
+        // Rows are counted from the bottom, `(2, 2)` is the top-left tile of the chunk.
+        let top_left = tile_at(&world, 0, 1).unwrap();
+        let bottom_left = tile_at(&world, 0, 0).unwrap();
+        assert_eq!(
+            tile(&world, top_left),
+            (
+                1,
+                TileFlip {
+                    x: true,
+                    y: false,
+                    d: false
+                },
+                Some(tilemap)
+            )
+        );
+        assert_eq!(
+            tile(&world, bottom_left),
+            (3, TileFlip::default(), Some(tilemap))
+        );
+        assert_eq!(tile_at(&world, 1, 0), None);
+        assert_eq!(tile_at(&world, 1, 1), None);
+
+        let transform = world.get::<Transform>(tilemap).unwrap();
+        assert_eq!(transform.translation, Vec3::new(40., 48., 0.));
+        assert_eq!(transform.scale, Vec3::new(1., -1., 1.));
+
+        let mut chunk = world.get_mut::<TileChunk>(tilemap).unwrap();
+        chunk.set(TilePos::new(2, 2), Some(chunk_tile(2, false, false)));
+        chunk.set(TilePos::new(3, 2), Some(chunk_tile(4, false, true)));
+        chunk.set(TilePos::new(2, 3), None);
+        world.run_system_once(sync_ecs_tilemaps);
+
+        // Changed tiles keep their entity, removed ones are despawned.
+        assert_eq!(tile_at(&world, 0, 1), Some(top_left));
+        assert_eq!(
+            tile(&world, top_left),
+            (2, TileFlip::default(), Some(tilemap))
+        );
+        let top_right = tile_at(&world, 1, 1).unwrap();
+        assert_eq!(
+            tile(&world, top_right),
+            (
+                4,
+                TileFlip {
+                    x: false,
+                    y: false,
+                    d: true
+                },
+                Some(tilemap)
+            )
+        );
+        assert_eq!(tile_at(&world, 0, 0), None);
+        assert!(world.get_entity(bottom_left).is_none());
+    }
+}
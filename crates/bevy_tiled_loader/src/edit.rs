@@ -0,0 +1,490 @@
+//! Changing the tiles of spawned tile layers at runtime.
+
+use std::iter::successors;
+
+use bevy::ecs::world::Command;
+use bevy::prelude::*;
+use tiled_parse::data_types::{LayerHierarchy, LayerTile, TileLayer, TiledLayer};
+
+use crate::chunks::{needs_tile_entity, set_chunk_tile};
+use crate::classes::TiledClassRegistry;
+#[cfg(feature = "colliders")]
+use crate::colliders::MergedTileRects;
+use crate::error::TiledLoaderError;
+use crate::load::{locate_tile, spawn_tile, MapSpawnContext};
+#[cfg(feature = "colliders")]
+use crate::load::{merges_colliders, set_merged_collider};
+use crate::types::{LayerOpacity, TilePos, TileStorage, TiledLayerId, TiledMapAsset};
+
+/// Changes tiles of a spawned tile layer, respawning their entities with their sprite, class
+/// component and colliders, and updating the layer's [`TileStorage`],
+/// [`TileChunk`](crate::types::TileChunk)s and merged collider.
+///
+/// The layer must be below an entity holding the handle of its map, like a
+/// [`TiledMapBundle`](crate::types::TiledMapBundle).
+///
+/// ```ignore
+/// commands.add(EditTiles::set(layer, TilePos::new(3, 4), tile).write_through());
+/// ```
+pub struct EditTiles {
+    layer: Entity,
+    min: TilePos,
+    max: TilePos,
+    tile: Option<LayerTile>,
+    write_through: bool,
+}
+
+impl EditTiles {
+    /// Sets the tile at `pos`.
+    pub fn set(layer: Entity, pos: TilePos, tile: LayerTile) -> Self {
+        Self::fill(layer, pos, pos, Some(tile))
+    }
+
+    /// Removes the tile at `pos`.
+    pub fn clear(layer: Entity, pos: TilePos) -> Self {
+        Self::fill(layer, pos, pos, None)
+    }
+
+    /// Sets (or with `None`, removes) the tiles from `min` to `max`, inclusive.
+    pub fn fill(layer: Entity, min: TilePos, max: TilePos, tile: Option<LayerTile>) -> Self {
+        EditTiles {
+            layer,
+            min,
+            max,
+            tile,
+            write_through: false,
+        }
+    }
+
+    /// Also changes the tiles in the [`TiledMapAsset`] of the layer, both its `map` and its
+    /// `scene`, so that maps spawned from it afterwards have them.
+    pub fn write_through(mut self) -> Self {
+        self.write_through = true;
+        self
+    }
+
+    /// Positions of the edited tiles that are on the grid.
+    fn positions(&self, size: UVec2) -> impl Iterator<Item = TilePos> {
+        let min = self.min;
+        let (end_x, end_y) = (
+            self.max.x.saturating_add(1).min(size.x),
+            self.max.y.saturating_add(1).min(size.y),
+        );
+
+        (min.y..end_y).flat_map(move |y| (min.x..end_x).map(move |x| TilePos::new(x, y)))
+    }
+
+    fn edit_error(&self, reason: &str) -> TiledLoaderError {
+        TiledLoaderError::TileEdit {
+            layer: self.layer,
+            reason: reason.into(),
+        }
+    }
+
+    fn try_apply(&self, world: &mut World) -> Result<(), TiledLoaderError> {
+        let layer = self.layer;
+
+        let TiledLayerId(layer_id) = *world
+            .get::<TiledLayerId>(layer)
+            .ok_or_else(|| self.edit_error("it isn't a tile layer"))?;
+        let map = successors(Some(layer), |e| world.get::<Parent>(*e).map(Parent::get))
+            .find_map(|e| world.get::<Handle<TiledMapAsset>>(e))
+            .cloned()
+            .ok_or_else(|| self.edit_error("no entity above it holds the handle of its map"))?;
+
+        let type_registry = world.resource::<AppTypeRegistry>().clone();
+        let classes = world
+            .get_resource_or_insert_with(TiledClassRegistry::default)
+            .clone();
+
+        world.resource_scope(|world, mut map_assets: Mut<Assets<TiledMapAsset>>| {
+            {
+                let map_asset = map_assets
+                    .get(&map)
+                    .ok_or_else(|| self.edit_error("its map isn't loaded"))?;
+                let type_registry = type_registry.read();
+                let ctx = MapSpawnContext::of(map_asset, &type_registry, &classes);
+
+                self.edit_layer(world, layer, &ctx)?;
+
+                if self.write_through {
+                    world.resource_scope(|_, mut scenes: Mut<Assets<Scene>>| {
+                        let scene_world = &mut scenes
+                            .get_mut(&map_asset.scene)
+                            .ok_or_else(|| self.edit_error("the scene of its map isn't loaded"))?
+                            .world;
+                        let scene_layer = scene_world
+                            .query_filtered::<(Entity, &TiledLayerId), With<TileStorage>>()
+                            .iter(scene_world)
+                            .find_map(|(e, TiledLayerId(id))| (*id == layer_id).then_some(e))
+                            .ok_or_else(|| {
+                                self.edit_error("the scene of its map doesn't have it")
+                            })?;
+
+                        self.edit_layer(scene_world, scene_layer, &ctx)
+                    })?;
+                }
+            }
+
+            if self.write_through {
+                let tile_layer = map_assets
+                    .get_mut(&map)
+                    .and_then(|map_asset| tile_layer_mut(&mut map_asset.map.layers, layer_id))
+                    .ok_or_else(|| self.edit_error("its map doesn't have it"))?;
+
+                let (width, height) = tile_layer.content.dim();
+                for TilePos { x, y } in self.positions(UVec2::new(width as u32, height as u32)) {
+                    tile_layer.content[(x as usize, y as usize)] = self.tile;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Edits the tiles of `layer`, in either the world the map was spawned in or the world of its
+    /// scene.
+    fn edit_layer(
+        &self,
+        world: &mut World,
+        layer: Entity,
+        ctx: &MapSpawnContext,
+    ) -> Result<(), TiledLoaderError> {
+        let size = world
+            .get::<TileStorage>(layer)
+            .ok_or_else(|| self.edit_error("it isn't a tile layer"))?
+            .size();
+        let layer_name = world
+            .get::<Name>(layer)
+            .map_or_else(String::new, |n| n.as_str().into());
+        let opacity = successors(Some(layer), |e| world.get::<Parent>(*e).map(Parent::get))
+            .filter_map(|e| world.get::<LayerOpacity>(e))
+            .map(|LayerOpacity(o)| *o)
+            .product();
+
+        #[cfg(feature = "colliders")]
+        let merge_colliders = merges_colliders(ctx.settings);
+
+        for pos in self.positions(size) {
+            let old = world
+                .get_mut::<TileStorage>(layer)
+                .and_then(|mut storage| storage.remove(pos));
+            if let Some(old) = old.and_then(|old| world.get_entity_mut(old)) {
+                old.despawn_recursive();
+            }
+
+            set_chunk_tile(
+                world,
+                layer,
+                &layer_name,
+                size,
+                pos,
+                self.tile,
+                opacity,
+                ctx,
+            )?;
+
+            // NOTE:
+            // Tiles drawn by chunks only need an entity for what they carry.
+            let needs_entity = |tile: &LayerTile| {
+                ctx.settings.chunk_size().is_none()
+                    || locate_tile(ctx.tile_sets, tile.tile).is_some_and(|(_, tileset, index)| {
+                        needs_tile_entity(tileset.tile_stuff.get(&index))
+                    })
+            };
+
+            if let Some(tile) = self.tile.filter(needs_entity) {
+                let tile_ent = spawn_tile(
+                    world,
+                    layer,
+                    &layer_name,
+                    pos,
+                    tile,
+                    opacity,
+                    ctx,
+                    // NOTE:
+                    // The rectangles are kept by the tile, and gathered again below.
+                    #[cfg(feature = "colliders")]
+                    merge_colliders.then_some(&mut Vec::new()),
+                )?;
+
+                if let Some(mut storage) = world.get_mut::<TileStorage>(layer) {
+                    storage.set(pos, tile_ent);
+                }
+            }
+        }
+
+        #[cfg(feature = "colliders")]
+        if merge_colliders {
+            let rects = world
+                .get::<TileStorage>(layer)
+                .into_iter()
+                .flat_map(TileStorage::iter)
+                .filter_map(|(_, tile)| world.get::<MergedTileRects>(tile))
+                .flat_map(|MergedTileRects(rects)| rects.iter().copied())
+                .collect::<Vec<_>>();
+
+            set_merged_collider(world, layer, &layer_name, &rects, ctx.settings)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Command for EditTiles {
+    fn apply(self, world: &mut World) {
+        if let Err(e) = self.try_apply(world) {
+            error!("{e}");
+        }
+    }
+}
+
+fn tile_layer_mut(node: &mut LayerHierarchy, id: u32) -> Option<&mut TileLayer> {
+    match node {
+        LayerHierarchy::Leaf(TiledLayer::Tile(tile_layer)) if tile_layer.id == id => {
+            Some(tile_layer)
+        }
+        LayerHierarchy::Node(_, children) => children
+            .iter_mut()
+            .find_map(|child| tile_layer_mut(child, id)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array2;
+    use tiled_parse::data_types::{Gid, Object, TileAuxInfo, TiledPropertyType};
+
+    use crate::test_util::{layer, layer_tile, map, object, properties, tile_set};
+    use crate::types::{TileChunk, TileRenderMode, TiledLoaderSettings};
+
+    /// A map with an empty 3 by 2 tile layer, spawned in the world and in the map's scene. Tile
+    /// `1` is plain, tile `2` is a wall with a collision rectangle.
+    fn spawned_map(settings: TiledLoaderSettings) -> (World, Entity) {
+        let wall = Object {
+            properties: properties(&[("collider", TiledPropertyType::Bool(true))]),
+            ..object("", "")
+        };
+
+        let mut world = World::new();
+        world.init_resource::<AppTypeRegistry>();
+        world.init_resource::<Assets<TiledMapAsset>>();
+        world.init_resource::<Assets<Scene>>();
+
+        let empty_layer = || {
+            (
+                Name::new("Tiles"),
+                TiledLayerId(1),
+                TileStorage::new(UVec2::new(3, 2)),
+            )
+        };
+
+        let mut scene_world = World::new();
+        scene_world.spawn(empty_layer());
+        let scene = world
+            .resource_mut::<Assets<Scene>>()
+            .add(Scene::new(scene_world));
+
+        let map = world
+            .resource_mut::<Assets<TiledMapAsset>>()
+            .add(TiledMapAsset {
+                map: map(
+                    (3, 2),
+                    vec![TiledLayer::Tile(layer(
+                        1,
+                        "Tiles",
+                        Array2::from_elem((3, 2), None),
+                    ))],
+                    vec![tile_set([(
+                        1,
+                        TileAuxInfo {
+                            class: String::new(),
+                            properties: Default::default(),
+                            objects: vec![wall],
+                        },
+                    )])],
+                ),
+                settings,
+                tilemap_textures: vec![Handle::default()],
+                tilemap_atlases: vec![Handle::default()],
+                tilemap_materials: vec![Handle::default()],
+                #[cfg(feature = "sprite3d")]
+                tilemap_standard_materials: vec![Handle::default()],
+                scene,
+            });
+
+        let layer = world.spawn(empty_layer()).id();
+        world.spawn(map).add_child(layer);
+
+        (world, layer)
+    }
+
+    fn stored(world: &World, layer: Entity, x: u32, y: u32) -> Option<Entity> {
+        world
+            .get::<TileStorage>(layer)
+            .unwrap()
+            .get(TilePos::new(x, y))
+    }
+
+    /// Index of the tile drawn at a position by the chunks of the layer.
+    fn chunk_index(world: &World, layer: Entity, x: u32, y: u32) -> Option<u32> {
+        world
+            .get::<Children>(layer)?
+            .iter()
+            .filter_map(|&e| world.get::<TileChunk>(e))
+            .find_map(|chunk| chunk.get(TilePos::new(x, y)))
+            .map(|tile| tile.index)
+    }
+
+    #[test]
+    fn edits_respawn_tile_entities() {
+        let (mut world, layer) = spawned_map(TiledLoaderSettings::default());
+
+        EditTiles::set(layer, TilePos::new(1, 1), layer_tile(1))
+            .try_apply(&mut world)
+            .unwrap();
+        let plain = stored(&world, layer, 1, 1).unwrap();
+        assert_eq!(world.get::<TilePos>(plain), Some(&TilePos::new(1, 1)));
+        assert_eq!(world.get::<Parent>(plain).map(Parent::get), Some(layer));
+
+        EditTiles::set(layer, TilePos::new(1, 1), layer_tile(2))
+            .try_apply(&mut world)
+            .unwrap();
+        let wall = stored(&world, layer, 1, 1).unwrap();
+        assert!(world.get_entity(plain).is_none());
+        assert_eq!(world.get::<TextureAtlas>(wall).map(|a| a.index), Some(1));
+
+        // Filling past the edge of the grid stops there.
+        EditTiles::fill(
+            layer,
+            TilePos::new(0, 0),
+            TilePos::new(u32::MAX, 0),
+            Some(layer_tile(1)),
+        )
+        .try_apply(&mut world)
+        .unwrap();
+        assert!((0..3).all(|x| stored(&world, layer, x, 0).is_some()));
+        assert_eq!(stored(&world, layer, 1, 1), Some(wall));
+
+        EditTiles::clear(layer, TilePos::new(1, 1))
+            .try_apply(&mut world)
+            .unwrap();
+        assert_eq!(stored(&world, layer, 1, 1), None);
+        assert!(world.get_entity(wall).is_none());
+    }
+
+    #[test]
+    fn chunks_draw_plain_tiles() {
+        let (mut world, layer) = spawned_map(TiledLoaderSettings {
+            render_mode: TileRenderMode::Chunks { size: 2 },
+            ..Default::default()
+        });
+
+        EditTiles::set(layer, TilePos::new(0, 0), layer_tile(1))
+            .try_apply(&mut world)
+            .unwrap();
+        EditTiles::set(layer, TilePos::new(2, 1), layer_tile(2))
+            .try_apply(&mut world)
+            .unwrap();
+
+        // Only the wall carries something its chunk can't.
+        assert_eq!(stored(&world, layer, 0, 0), None);
+        assert_eq!(chunk_index(&world, layer, 0, 0), Some(0));
+        let wall = stored(&world, layer, 2, 1).unwrap();
+        assert_eq!(chunk_index(&world, layer, 2, 1), Some(1));
+
+        EditTiles::clear(layer, TilePos::new(0, 0))
+            .try_apply(&mut world)
+            .unwrap();
+        assert_eq!(chunk_index(&world, layer, 0, 0), None);
+        assert_eq!(chunk_index(&world, layer, 2, 1), Some(1));
+
+        EditTiles::fill(
+            layer,
+            TilePos::new(0, 0),
+            TilePos::new(u32::MAX, u32::MAX),
+            None,
+        )
+        .try_apply(&mut world)
+        .unwrap();
+        assert_eq!(chunk_index(&world, layer, 2, 1), None);
+        assert_eq!(world.get::<TileStorage>(layer).unwrap().iter().count(), 0);
+        assert!(world.get_entity(wall).is_none());
+    }
+
+    #[test]
+    fn write_through_edits_the_map_and_its_scene() {
+        let (mut world, layer) = spawned_map(TiledLoaderSettings::default());
+
+        EditTiles::set(layer, TilePos::new(2, 0), layer_tile(2))
+            .write_through()
+            .try_apply(&mut world)
+            .unwrap();
+        EditTiles::set(layer, TilePos::new(0, 1), layer_tile(1))
+            .try_apply(&mut world)
+            .unwrap();
+
+        let (_, map_asset) = world
+            .resource::<Assets<TiledMapAsset>>()
+            .iter()
+            .next()
+            .unwrap();
+        let Some(TiledLayer::Tile(tile_layer)) = map_asset.map.layers.iter().nth(1) else {
+            panic!("The map should have its tile layer");
+        };
+        assert_eq!(tile_layer.content[(2, 0)].map(|t| t.tile), Some(Gid(2)));
+        assert!(tile_layer.content[(0, 1)].is_none());
+
+        let scene = map_asset.scene.clone();
+        let mut scenes = world.resource_mut::<Assets<Scene>>();
+        let scene_world = &mut scenes.get_mut(&scene).unwrap().world;
+        let storage = scene_world
+            .query::<&TileStorage>()
+            .single(scene_world)
+            .clone();
+        let scene_tile = storage.get(TilePos::new(2, 0)).unwrap();
+        assert_eq!(
+            scene_world.get::<TilePos>(scene_tile),
+            Some(&TilePos::new(2, 0))
+        );
+        assert_eq!(storage.get(TilePos::new(0, 1)), None);
+    }
+
+    #[cfg(feature = "colliders")]
+    #[test]
+    fn merged_collider_follows_edits() {
+        use crate::types::{Serialized, TileColliders};
+
+        let (mut world, layer) = spawned_map(TiledLoaderSettings {
+            tile_colliders: TileColliders::Merged,
+            ..Default::default()
+        });
+
+        EditTiles::fill(
+            layer,
+            TilePos::new(0, 0),
+            TilePos::new(1, 0),
+            Some(layer_tile(2)),
+        )
+        .try_apply(&mut world)
+        .unwrap();
+        assert!(world.get::<Serialized>(layer).is_some());
+        assert!((0..2).all(|x| {
+            stored(&world, layer, x, 0)
+                .and_then(|tile| world.get::<MergedTileRects>(tile))
+                .is_some_and(|MergedTileRects(rects)| rects.len() == 1)
+        }));
+
+        EditTiles::clear(layer, TilePos::new(0, 0))
+            .try_apply(&mut world)
+            .unwrap();
+        assert!(world.get::<Serialized>(layer).is_some());
+
+        EditTiles::clear(layer, TilePos::new(1, 0))
+            .try_apply(&mut world)
+            .unwrap();
+        assert!(world.get::<Serialized>(layer).is_none());
+    }
+}
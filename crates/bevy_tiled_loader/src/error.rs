@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use bevy::ecs::entity::Entity;
+
+use thiserror::Error;
+use tiled_parse::error::TiledParseError;
+
+/// Everything that can go wrong while [`TiledLoader`](crate::load::TiledLoader) loads a map.
+///
+/// Bevy reports it together with the path of the map in its asset load failure events.
+#[derive(Debug, Error)]
+pub enum TiledLoaderError {
+    #[error("Could not read TMX map: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("TMX map is not valid UTF-8: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("Could not parse TMX map: {0}")]
+    Parse(#[from] TiledParseError),
+    #[error("Tile with GID {gid} in layer `{layer}` does not belong to any tileset")]
+    MissingTileset { gid: u32, layer: String },
+    #[error("Tileset `{tileset}` has an invalid image reference `{image}`")]
+    BadImage { tileset: String, image: PathBuf },
+    #[error("Could not build a collider for object {object_id}: {reason}")]
+    Collider { object_id: u32, reason: String },
+    #[error("Invalid physics properties on tile {tile} of tileset `{tileset}`: {reason}")]
+    TileProperties {
+        tileset: String,
+        tile: u32,
+        reason: String,
+    },
+    #[error("Could not build the merged collider of layer `{layer}`: {reason}")]
+    LayerCollider { layer: String, reason: String },
+    #[error("Could not insert the component of class `{class}`: {reason}")]
+    Class { class: String, reason: String },
+    #[error("Could not edit the tiles of layer {layer}: {reason}")]
+    TileEdit { layer: Entity, reason: String },
+}
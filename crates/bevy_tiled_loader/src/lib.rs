@@ -1,4 +1,18 @@
+mod chunks;
+pub mod classes;
+#[cfg(feature = "colliders")]
+mod colliders;
+#[cfg(feature = "ecs_tilemap")]
+mod ecs_tilemap;
+pub mod edit;
+pub mod error;
 pub mod load;
+#[cfg(feature = "colliders")]
+mod merge;
+#[cfg(feature = "sprite3d")]
+mod plane3d;
 pub mod plugin;
-pub mod relations;
+pub mod systems;
+#[cfg(test)]
+mod test_util;
 pub mod types;
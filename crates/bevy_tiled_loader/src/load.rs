@@ -1,60 +1,80 @@
-use std::fs::read_to_string;
+use std::collections::BTreeMap;
+use std::f32::consts::FRAC_PI_2;
 
-use bevy::asset::{io::Reader, AssetLoader, AssetPath, AsyncReadExt, AsyncWriteExt};
 use bevy::asset::{Asset, Handle, LoadContext};
-use bevy::ecs::reflect;
+use bevy::asset::{AssetLoader, AssetPath, AsyncReadExt};
 use bevy::hierarchy::BuildWorldChildren;
 use bevy::math::{UVec2, Vec2};
 use bevy::prelude::*;
-use bevy::prelude::{SpatialBundle, TransformBundle};
+use bevy::reflect::TypeRegistry;
 use bevy::scene::Scene;
-use bevy::sprite::{Anchor, Sprite, SpriteBundle, TextureAtlas, TextureAtlasLayout};
-use bevy::transform::components::{GlobalTransform, Transform};
+use bevy::sprite::{Anchor, ColorMaterial, Sprite, SpriteBundle, TextureAtlas, TextureAtlasLayout};
 use bevy::utils::hashbrown::HashMap;
 
-#[cfg(feature = "rapier2d_colliders")]
-use bevy_rapier2d::prelude::*;
 use tiled_parse::relations::{get_tile_id, get_tileset_for_gid};
 
-use crate::types::{SceneSerializedComponents, Serialized, TiledMapAsset, TiledMapContainer};
+use crate::chunks::{chunk_tile, needs_tile_entity, new_chunk, spawn_chunk};
+use crate::classes::{insert_class_component, TiledClassRegistry};
+#[cfg(feature = "colliders")]
+use crate::colliders::{
+    add_colliders, add_object_collider, merged_collider, remove_collider, serialize_collider,
+    tile_physics, tile_rect, BodyProperties, ColliderProperties, MergedTileRects, ShapesPlacement,
+};
+use crate::error::TiledLoaderError;
+#[cfg(feature = "sprite3d")]
+use crate::plane3d::{
+    extrusion, layer_height, map_rotation, spawn_tile_object_quad, tileset_material,
+};
+use crate::types::{
+    LayerOpacity, LayerParallax, MapOrigin, TileLayerId, TilePos, TileStorage, TiledLayerId,
+    TiledLoaderSettings, TiledMapAsset, TiledMapContainer, TiledObjectEntities, TiledObjectId,
+    TiledObjectRefs, TiledObjectShape, TiledProperties, TiledProperty, YAxis,
+};
 use tiled_parse::data_types::*;
 use tiled_parse::parse::*;
 
 /// Allows us to do `AssetServer.load("MY_MAP.tmx")`
-#[derive(Default)]
-pub struct TiledLoader;
+pub struct TiledLoader {
+    type_registry: AppTypeRegistry,
+    classes: TiledClassRegistry,
+}
+
+impl FromWorld for TiledLoader {
+    fn from_world(world: &mut World) -> Self {
+        TiledLoader {
+            type_registry: world.resource::<AppTypeRegistry>().clone(),
+            classes: world
+                .get_resource_or_insert_with(TiledClassRegistry::default)
+                .clone(),
+        }
+    }
+}
 
 pub const MAP_SCENE: &str = "MapScene";
 
-// TODO:
-// Improved error
 impl AssetLoader for TiledLoader {
     type Asset = TiledMapAsset;
-    type Settings = ();
-    type Error = std::io::Error;
+    type Settings = TiledLoaderSettings;
+    type Error = TiledLoaderError;
 
     fn load<'a>(
         &'a self,
         reader: &'a mut bevy::asset::io::Reader,
-        _settings: &'a Self::Settings,
+        settings: &'a Self::Settings,
         load_context: &'a mut bevy::asset::LoadContext,
     ) -> bevy::utils::BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
         Box::pin(async move {
             let mut data = Vec::new();
             reader.read_to_end(&mut data).await?;
 
-            let data_as_utf8 = std::str::from_utf8(&data).map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Could not load TMX map: {e}"),
-                )
-            })?;
+            let data_as_utf8 = std::str::from_utf8(&data)?;
 
-            let tm: TiledMap = parse(data_as_utf8).map_err(|e| {
-                std::io::Error::new(std::io::ErrorKind::Other, format!("Could not load TMX map"))
-            })?;
+            let tm: TiledMap = parse(data_as_utf8)?;
 
-            load_tmx(load_context, tm)
+            // NOTE:
+            // The registry is locked for the rest of the (synchronous) loading.
+            let type_registry = self.type_registry.read();
+            load_tmx(load_context, tm, settings, &type_registry, &self.classes)
         })
     }
 
@@ -64,7 +84,13 @@ impl AssetLoader for TiledLoader {
     }
 }
 
-fn load_tmx(load_context: &mut LoadContext, tm: TiledMap) -> Result<TiledMapAsset, std::io::Error> {
+fn load_tmx(
+    load_context: &mut LoadContext,
+    tm: TiledMap,
+    settings: &TiledLoaderSettings,
+    type_registry: &TypeRegistry,
+    classes: &TiledClassRegistry,
+) -> Result<TiledMapAsset, TiledLoaderError> {
     // TODO:
     // Might need some way to get tilemap_texture from a Tile's GID (To get the tile's texture).
     let TiledMap {
@@ -72,15 +98,27 @@ fn load_tmx(load_context: &mut LoadContext, tm: TiledMap) -> Result<TiledMapAsse
         grid_size,
         tile_size,
         tile_sets,
+        parallax_origin,
+        skipped_layers,
     } = &tm;
 
+    skipped_layers.iter().for_each(|layer| {
+        warn!(
+            "{layer} of {} isn't handled yet, and was skipped",
+            load_context.path().display()
+        )
+    });
+
     // TODO:
     // Review how tile set images are stored into these `Vec`s.
     // Some tilesets have more than one image (check TMX docs to verify what that actually means)
     let mut tilemap_textures = Vec::with_capacity(tile_sets.len());
     let mut tilemap_atlases = Vec::with_capacity(tile_sets.len());
+    let mut tilemap_materials = Vec::with_capacity(tile_sets.len());
+    #[cfg(feature = "sprite3d")]
+    let mut tilemap_standard_materials = Vec::with_capacity(tile_sets.len());
 
-    tile_sets.iter().for_each(|ts| {
+    tile_sets.iter().try_for_each(|ts| {
         let TileSet {
             tile_size,
             first_gid,
@@ -89,6 +127,7 @@ fn load_tmx(load_context: &mut LoadContext, tm: TiledMap) -> Result<TiledMapAsse
             margin,
             image,
             tile_stuff,
+            ..
         } = ts;
 
         let tiled_parse::data_types::Image {
@@ -97,10 +136,12 @@ fn load_tmx(load_context: &mut LoadContext, tm: TiledMap) -> Result<TiledMapAsse
             dimensions: (columns, rows),
         } = image;
 
-        let tmx_dir = load_context
-            .path()
-            .parent()
-            .expect("The asset load context was empty.");
+        let bad_image = || TiledLoaderError::BadImage {
+            tileset: name.clone(),
+            image: source.clone(),
+        };
+
+        let tmx_dir = load_context.path().parent().ok_or_else(bad_image)?;
         let tile_path = tmx_dir.join(&source);
         let asset_path = AssetPath::from(tile_path);
 
@@ -108,14 +149,8 @@ fn load_tmx(load_context: &mut LoadContext, tm: TiledMap) -> Result<TiledMapAsse
 
         let file_name = source
             .file_name()
-            .expect("Should have file name")
-            .to_str()
-            .expect("Valid utf8");
-
-        println!(
-            "tilesize : {}, {} | rowCols : {}, {} | spaceMarg : {}, {}",
-            tile_size.0, tile_size.1, rows, columns, spacing, margin
-        );
+            .and_then(|f| f.to_str())
+            .ok_or_else(bad_image)?;
 
         // TODO:
         // I don't know if I should use "add_labeled_asset", and if the arguments are
@@ -134,9 +169,25 @@ fn load_tmx(load_context: &mut LoadContext, tm: TiledMap) -> Result<TiledMapAsse
                 ),
             );
 
+        let material: Handle<ColorMaterial> = load_context.add_labeled_asset(
+            format!("{file_name}/material"),
+            ColorMaterial::from(texture_handle.clone()),
+        );
+
+        #[cfg(feature = "sprite3d")]
+        if settings.plane3d().is_some() {
+            tilemap_standard_materials.push(load_context.add_labeled_asset(
+                format!("{file_name}/standard_material"),
+                tileset_material(texture_handle.clone(), extrusion(settings).is_some()),
+            ));
+        }
+
         tilemap_textures.push(texture_handle);
         tilemap_atlases.push(texture_atlas);
-    });
+        tilemap_materials.push(material);
+
+        Ok::<_, TiledLoaderError>(())
+    })?;
 
     // Load scene
     let scene = {
@@ -147,106 +198,77 @@ fn load_tmx(load_context: &mut LoadContext, tm: TiledMap) -> Result<TiledMapAsse
 
         let mut layer_ents = Vec::new();
 
-        let tile_size_f32 = (tile_size.0 as f32, tile_size.1 as f32);
-
-        // let mut tile_ents = Vec::new();
-
-        layers.iter().enumerate().for_each(|(i, x)| match x {
-            // TODO:
-            // Handle other layer types
-            TiledLayer::Tile(Layer { name, content, .. }) => {
-                // TODO:
-                // Assigning z-index to `i` won't work for GroupLayers because `layers` currently iterate as a breadth first
-                // iterator...
-                let mut spatial_bundle = SpatialBundle::INHERITED_IDENTITY;
-                spatial_bundle.transform.translation = Vec2::ZERO.extend(i as f32);
-
-                let layer_ent = world.spawn((Name::new(name.clone()), spatial_bundle)).id();
-
-                layer_ents.push(layer_ent);
-
-                let mut tile_ents = Vec::new();
-
-                content
-                    .indexed_iter()
-                    .filter_map(|(p, t)| t.map(|v| (p, v)))
-                    .for_each(
-                        |(
-                            tile_pos,
-                            LayerTile {
-                                tile: Gid(tile_gid),
-                                flip_h,
-                                flip_v,
-                                flip_d,
-                            },
-                        )| {
-                            let (world_pos_x, world_pos_y) = (
-                                tile_size_f32.0 * tile_pos.0 as f32,
-                                -tile_size_f32.1 * tile_pos.1 as f32,
-                            );
-
-                            let tile_tileset = get_tileset_for_gid(tile_sets, Gid(tile_gid))
-                                .expect("Tile should belong to tileset");
-
-                            let tileset_index = tile_sets
-                                .iter()
-                                .position(|ts| ts.first_gid == tile_tileset.first_gid)
-                                .expect("Yes");
-
-                            let local_tile_id = get_tile_id(tile_tileset, Gid(tile_gid));
-
-                            let tile_aux_info_opt = tile_tileset.tile_stuff.get(&local_tile_id);
-
-                            let mut tile_entity = world.spawn((
-                                SpriteBundle {
-                                    sprite: Sprite {
-                                        flip_x: flip_h,
-                                        flip_y: flip_v,
-                                        anchor: Anchor::TopLeft,
-                                        ..Default::default()
-                                    },
-                                    transform: Transform::from_xyz(world_pos_x, world_pos_y, 0.),
-                                    // TODO:
-                                    // Don't just get the `0` item
-                                    texture: tilemap_textures.get(tileset_index).unwrap().clone(),
-                                    ..Default::default()
-                                },
-                                TextureAtlas {
-                                    // TODO:
-                                    // Don't just get the `0` item
-                                    layout: tilemap_atlases.get(tileset_index).unwrap().clone(),
-                                    index: local_tile_id as usize,
-                                },
-                            ));
-
-                            if let Some(tile_aux_info) = tile_aux_info_opt {
-                                #[cfg(feature = "rapier2d_colliders")]
-                                {
-                                    add_colliders(&mut tile_entity, &tile_aux_info.objects);
-                                }
-                            }
-
-                            tile_entity.set_parent(layer_ent);
-
-                            // NOTE:
-                            // There is an assumption that it's being loaded for a 2d camera here.
-                            tile_ents.push(tile_entity.id());
-                        },
-                    );
-            }
-            _ => {
-                println!("Layer was not a `Tile` layer. Not currently handled.");
-            }
-        });
+        let ctx = MapSpawnContext {
+            tile_size: Vec2::new(tile_size.0 as f32, tile_size.1 as f32),
+            parallax_origin: Vec2::new(parallax_origin.0, settings.y_sign() * parallax_origin.1),
+            tile_sets,
+            tilemap_textures: &tilemap_textures,
+            tilemap_atlases: &tilemap_atlases,
+            tilemap_materials: &tilemap_materials,
+            #[cfg(feature = "sprite3d")]
+            tilemap_standard_materials: &tilemap_standard_materials,
+            settings,
+            type_registry,
+            classes,
+        };
+
+        let y_sign = settings.y_sign();
+
+        // NOTE:
+        // The root of the hierarchy is the map itself. Its children go directly under the
+        // container.
+        let mut draw_index = 0;
+        let mut map_class = None;
+        if let LayerHierarchy::Node(TiledLayer::Group(map), children) = layers {
+            map_class = Some((&map.class, &map.properties));
+            children.iter().try_for_each(|child| {
+                layer_ents.extend(spawn_layer_tree(
+                    &mut world,
+                    child,
+                    0,
+                    &mut draw_index,
+                    Inherited::ROOT,
+                    &ctx,
+                )?);
+
+                Ok::<_, TiledLoaderError>(())
+            })?;
+        }
+
+        // NOTE:
+        // The container carries the origin and the pixels-per-unit scale so that everything below
+        // it can stay in Tiled's pixel units.
+        let map_size_px = Vec2::new(
+            (grid_size.0 * tile_size.0) as f32,
+            (grid_size.1 * tile_size.1) as f32,
+        );
+        let origin_offset = match settings.origin {
+            MapOrigin::TopLeft => Vec2::ZERO,
+            MapOrigin::Center => Vec2::new(-map_size_px.x, -y_sign * map_size_px.y) / 2.,
+        };
+        let mut container_bundle = SpatialBundle::INHERITED_IDENTITY;
+        container_bundle.transform =
+            Transform::from_translation((origin_offset / settings.pixels_per_unit).extend(0.))
+                .with_scale(Vec2::splat(settings.pixels_per_unit.recip()).extend(1.));
+        #[cfg(feature = "sprite3d")]
+        if settings.plane3d().is_some() {
+            container_bundle
+                .transform
+                .rotate_around(Vec3::ZERO, map_rotation());
+        }
+
+        let object_entities = resolve_object_refs(&mut world);
 
         // TODO:
         // I'm not convinced this `per-entity` thing is very good.
         let mut e_c = world.spawn((
             TiledMapContainer,
-            // TODO:
-            // There may be some situation where it won't just be 0 ?
-            SpatialBundle::INHERITED_IDENTITY,
+            TiledObjectEntities(object_entities),
+            container_bundle,
         ));
+        if let Some((class, properties)) = map_class {
+            insert_class_component(&mut e_c, class, properties, type_registry, classes)?;
+        }
         // e_c.push_children(&tile_ents);
         e_c.push_children(&layer_ents);
         e_c.set_parent(world_root_id);
@@ -258,175 +280,845 @@ fn load_tmx(load_context: &mut LoadContext, tm: TiledMap) -> Result<TiledMapAsse
 
     Ok(TiledMapAsset {
         map: tm,
+        settings: settings.clone(),
         scene,
         tilemap_textures,
         tilemap_atlases,
+        tilemap_materials,
+        #[cfg(feature = "sprite3d")]
+        tilemap_standard_materials,
     })
 }
 
-fn add_colliders(e: &mut EntityWorldMut, os: &Vec<Object>) {
-    e.with_children(|cb| {
-        os.iter()
-            .filter(|o| {
-                if let Some(TiledPropertyType::Bool(v)) = o.properties.get("collider") {
-                    *v
-                } else {
-                    false
-                }
-            })
-            .for_each(
-                |Object {
-                     position: (x, y),
-                     size,
-                     rotation,
-                     tile_global_id,
-                     visible,
-                     otype,
-                     properties,
-                     ..
-                 }| {
-                    if let ObjectType::Point = otype {
-                        return;
+/// Gives every entity with object-valued properties the entities of the objects they point at,
+/// and returns the entities of all objects by id.
+///
+/// Done once everything is spawned, as objects can point at objects spawned after them.
+fn resolve_object_refs(world: &mut World) -> HashMap<u32, Entity> {
+    let object_entities = world
+        .query::<(Entity, &TiledObjectId)>()
+        .iter(world)
+        .map(|(e, TiledObjectId(id))| (*id, e))
+        .collect::<HashMap<_, _>>();
+
+    let refs = world
+        .query::<(Entity, &TiledProperties)>()
+        .iter(world)
+        .filter_map(|(e, TiledProperties(properties))| {
+            // NOTE:
+            // Unset object properties are `0`, which no object has.
+            let refs = properties
+                .iter()
+                .filter_map(|(name, p)| match p {
+                    TiledProperty::Object(id) => {
+                        object_entities.get(id).map(|o| (name.clone(), *o))
                     }
+                    _ => None,
+                })
+                .collect::<HashMap<_, _>>();
 
-                    let (
-                        Vec2 {
-                            x: offset_x,
-                            y: offset_y,
-                        },
-                        collider,
-                    ) = construct_geometry(&otype, size.map(|(x, y)| Vec2 { x, y }), None);
-
-                    cb.spawn((
-                        TransformBundle::from_transform(
-                            Transform::from_xyz(*x + offset_x, -(*y + offset_y), 0.).with_rotation(
-                                Quat::from_axis_angle(Vec3::Z, rotation.to_radians()),
-                            ),
-                        ),
-                        Serialized {
-                            data: bincode::serialize(&collider)
-                                .expect("Expected to serialize collider"),
-                            thingy: SceneSerializedComponents::RCollider,
-                        },
-                    ));
-                },
-            )
+            (!refs.is_empty()).then_some((e, TiledObjectRefs(refs)))
+        })
+        .collect::<Vec<_>>();
+
+    refs.into_iter().for_each(|(e, refs)| {
+        world.entity_mut(e).insert(refs);
     });
+
+    object_entities
 }
 
-// fn handle_parallax(
-//     camera_trans_q: Query<&Transform, With<Camera>>,
-//     mut parallax_layer: Query<(&mut Transform, &LayerParallax), Without<Camera>>,
-// ) {
-//     let Ok(cam_transform) = camera_trans_q.get_single() else {
-//         return;
-//     };
-//
-//     parallax_layer
-//         .iter_mut()
-//         .for_each(|(mut layer_transform, layer_parallax)| {
-//             let dist_from_layer_center = cam_transform.translation - layer_parallax.offset;
-//
-//             layer_transform.translation = layer_parallax.center
-//                 - Vec3::new(
-//                     dist_from_layer_center.x * (layer_parallax.parallax.x - 1.),
-//                     dist_from_layer_center.y * (layer_parallax.parallax.y - 1.),
-//                     0.,
-//                 );
-//         })
-// }
-
-fn construct_geometry(
-    shape: &ObjectType,
-    size: Option<Vec2>,
-    scale_factor: Option<Vect>,
-) -> (Vec2, Collider) {
-    let scale_factor = scale_factor.unwrap_or(Vect::ONE);
-
-    match shape {
-        ObjectType::Rectangle => {
-            let Some(size) = size else { unreachable!() };
-            (
-                size / 2. * scale_factor,
-                Collider::cuboid(scale_factor.x * size.x / 2., scale_factor.y * size.y / 2.),
-            )
+/// Spawns a layer and, for groups, its descendants. Layers are given increasing `z` in the order
+/// of a depth-first traversal, which is the order Tiled draws them in.
+///
+/// `z` is relative to the parent group, hence `parent_index` being the parent's draw index.
+fn spawn_layer_tree(
+    world: &mut World,
+    node: &LayerHierarchy,
+    parent_index: usize,
+    draw_index: &mut usize,
+    inherited: Inherited,
+    ctx: &MapSpawnContext,
+) -> Result<Option<Entity>, TiledLoaderError> {
+    *draw_index += 1;
+    let index = *draw_index;
+    let z = (index - parent_index) as f32 * ctx.settings.layer_z_spacing;
+
+    match node {
+        LayerHierarchy::Leaf(TiledLayer::Tile(tile_layer)) if ctx.settings.spawn_layers.tiles => {
+            spawn_tile_layer(world, tile_layer, z, inherited, ctx).map(Some)
         }
-        ObjectType::Ellipse => {
-            let Some(size) = size else { unreachable!() };
-            todo!("Do it");
+        LayerHierarchy::Leaf(TiledLayer::Object(object_layer))
+            if ctx.settings.spawn_layers.objects =>
+        {
+            spawn_object_layer(world, object_layer, z, inherited, ctx).map(Some)
         }
-        ObjectType::Polygon(points) => (
-            Vec2::ZERO,
-            Collider::convex_hull(
-                &points
-                    .iter()
-                    .map(|(x, y)| Vect::new(scale_factor.x * *x, scale_factor.y * -*y))
-                    .collect::<Vec<_>>(),
-            )
-            .unwrap(),
-        ),
-        ObjectType::Polyline(points) => (
-            Vec2::ZERO,
-            Collider::polyline(
-                points
-                    .iter()
-                    .map(|p| Vec2 {
-                        x: scale_factor.x * p.0,
-                        y: scale_factor.y * -p.1,
+        LayerHierarchy::Node(TiledLayer::Group(group), children) => {
+            let group_ent = spawn_layer_entity(world, group, z, inherited, ctx)?.id();
+
+            let child_ents = children
+                .iter()
+                .filter_map(|child| {
+                    spawn_layer_tree(world, child, index, draw_index, inherited.with(group), ctx)
+                        .transpose()
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            world.entity_mut(group_ent).push_children(&child_ents);
+
+            Ok(Some(group_ent))
+        }
+        // NOTE:
+        // Image layers are skipped by the parser, and warned about by `load_tmx`.
+        _ => Ok(None),
+    }
+}
+
+/// What a layer gets from the groups it is in.
+#[derive(Clone, Copy)]
+struct Inherited {
+    opacity: f32,
+    parallax: Vec2,
+}
+
+impl Inherited {
+    const ROOT: Inherited = Inherited {
+        opacity: 1.,
+        parallax: Vec2::ONE,
+    };
+
+    /// What the children of `layer` inherit.
+    fn with<T>(self, layer: &Layer<T>) -> Self {
+        Inherited {
+            opacity: self.opacity * layer.opacity,
+            parallax: self.parallax * Vec2::new(layer.parallax.0, layer.parallax.1),
+        }
+    }
+}
+
+/// Spawns the entity every kind of layer is represented by. Its children are added by the caller.
+fn spawn_layer_entity<'w, T>(
+    world: &'w mut World,
+    layer: &Layer<T>,
+    z: f32,
+    inherited: Inherited,
+    ctx: &MapSpawnContext,
+) -> Result<EntityWorldMut<'w>, TiledLoaderError> {
+    let Layer {
+        id,
+        name,
+        visible,
+        opacity,
+        offset: (offset_x, offset_y),
+        class,
+        properties,
+        ..
+    } = layer;
+
+    #[cfg(feature = "sprite3d")]
+    let z = ctx
+        .settings
+        .plane3d()
+        .and_then(|plane3d| layer_height(properties, plane3d))
+        .unwrap_or(z);
+    let translation = Vec3::new(*offset_x, ctx.settings.y_sign() * *offset_y, z);
+
+    let mut layer_entity = world.spawn((
+        Name::new(name.clone()),
+        TiledLayerId(*id),
+        LayerOpacity(*opacity),
+        TiledProperties::from(properties),
+        SpatialBundle {
+            transform: Transform::from_translation(translation),
+            visibility: if *visible {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            },
+            ..Default::default()
+        },
+    ));
+
+    let factor = inherited.with(layer).parallax;
+    if factor != inherited.parallax {
+        layer_entity.insert(LayerParallax {
+            factor,
+            parent_factor: inherited.parallax,
+            origin: ctx.parallax_origin,
+            base_translation: translation,
+        });
+    }
+
+    insert_class_component(
+        &mut layer_entity,
+        class,
+        properties,
+        ctx.type_registry,
+        ctx.classes,
+    )?;
+
+    Ok(layer_entity)
+}
+
+fn spawn_tile_layer(
+    world: &mut World,
+    tile_layer: &TileLayer,
+    z: f32,
+    inherited: Inherited,
+    ctx: &MapSpawnContext,
+) -> Result<Entity, TiledLoaderError> {
+    let Layer { name, content, .. } = tile_layer;
+
+    let layer_ent = spawn_layer_entity(world, tile_layer, z, inherited, ctx)?.id();
+    let opacity = inherited.with(tile_layer).opacity;
+
+    let (width, height) = content.dim();
+    let layer_size = UVec2::new(width as u32, height as u32);
+    let mut storage = TileStorage::new(layer_size);
+    let mut chunks = BTreeMap::new();
+
+    #[cfg(feature = "colliders")]
+    let merge_colliders = merges_colliders(ctx.settings);
+    #[cfg(feature = "colliders")]
+    let mut merged_rects = Vec::new();
+
+    content
+        .indexed_iter()
+        .filter_map(|(p, t)| t.map(|v| (TilePos::new(p.0 as u32, p.1 as u32), v)))
+        .try_for_each(|(tile_pos, layer_tile)| {
+            if let Some(size) = ctx.settings.chunk_size() {
+                let Gid(gid) = layer_tile.tile;
+                let (tileset_index, tileset, index) = locate_tile(ctx.tile_sets, layer_tile.tile)
+                    .ok_or_else(|| {
+                    TiledLoaderError::MissingTileset {
+                        gid,
+                        layer: name.clone(),
+                    }
+                })?;
+
+                chunks
+                    .entry((tileset_index, tile_pos.y / size, tile_pos.x / size))
+                    .or_insert_with(|| {
+                        new_chunk(tile_pos, size, layer_size, tileset_index, opacity, ctx)
                     })
-                    .collect(),
-                None,
+                    .set(
+                        tile_pos,
+                        Some(chunk_tile(layer_tile, tileset, index, ctx.settings)),
+                    );
+
+                if !needs_tile_entity(tileset.tile_stuff.get(&index)) {
+                    return Ok(());
+                }
+            }
+
+            let tile_ent = spawn_tile(
+                world,
+                layer_ent,
+                name,
+                tile_pos,
+                layer_tile,
+                opacity,
+                ctx,
+                #[cfg(feature = "colliders")]
+                merge_colliders.then_some(&mut merged_rects),
+            )?;
+
+            storage.set(tile_pos, tile_ent);
+
+            Ok::<_, TiledLoaderError>(())
+        })?;
+
+    world.entity_mut(layer_ent).insert(storage);
+
+    chunks.into_iter().for_each(|((tileset_index, ..), chunk)| {
+        spawn_chunk(world, layer_ent, chunk, tileset_index, ctx);
+    });
+
+    #[cfg(feature = "colliders")]
+    if merge_colliders {
+        set_merged_collider(world, layer_ent, name, &merged_rects, ctx.settings)?;
+    }
+
+    Ok(layer_ent)
+}
+
+/// Whether the collision rectangles of the tiles of tile layers are merged into a collider of
+/// their layer.
+#[cfg(feature = "colliders")]
+pub(crate) fn merges_colliders(settings: &TiledLoaderSettings) -> bool {
+    settings.generate_colliders && settings.tile_colliders != crate::types::TileColliders::PerTile
+}
+
+/// Gives a tile layer the collider covering `rects`, or removes its collider when there are none.
+#[cfg(feature = "colliders")]
+pub(crate) fn set_merged_collider(
+    world: &mut World,
+    layer_ent: Entity,
+    layer_name: &str,
+    rects: &[Rect],
+    settings: &TiledLoaderSettings,
+) -> Result<(), TiledLoaderError> {
+    let mut layer_entity = world.entity_mut(layer_ent);
+    remove_collider(&mut layer_entity);
+
+    if !rects.is_empty() {
+        let collider =
+            serialize_collider(&merged_collider(rects, settings), settings).map_err(|reason| {
+                TiledLoaderError::LayerCollider {
+                    layer: layer_name.into(),
+                    reason,
+                }
+            })?;
+
+        layer_entity.insert(collider);
+    }
+
+    Ok(())
+}
+
+/// Spawns the entity of a tile of a tile layer, as a child of the layer's entity.
+///
+/// With `merged_rects`, the collision rectangles that can be merged into the layer's collider are
+/// pushed there instead of getting their own collider, and kept in the tile's [`MergedTileRects`].
+pub(crate) fn spawn_tile(
+    world: &mut World,
+    layer_ent: Entity,
+    layer_name: &str,
+    tile_pos: TilePos,
+    LayerTile {
+        tile: Gid(tile_gid),
+        flip_h,
+        flip_v,
+        flip_d,
+    }: LayerTile,
+    opacity: f32,
+    ctx: &MapSpawnContext,
+    #[cfg(feature = "colliders")] merged_rects: Option<&mut Vec<Rect>>,
+) -> Result<Entity, TiledLoaderError> {
+    let MapSpawnContext {
+        tile_sets,
+        tilemap_textures,
+        tilemap_atlases,
+        settings,
+        ..
+    } = ctx;
+
+    let (tileset_index, tile_tileset, local_tile_id) = locate_tile(tile_sets, Gid(tile_gid))
+        .ok_or_else(|| TiledLoaderError::MissingTileset {
+            gid: tile_gid,
+            layer: layer_name.into(),
+        })?;
+
+    let y_sign = settings.y_sign();
+
+    // NOTE:
+    // Tiles are laid out on the map's grid, not their tileset's.
+    // Tiled draws tiles from the bottom-left corner of their cell, which matters when the
+    // tileset's tiles are bigger than the map's. Sprites are centered so that they can be rotated
+    // in place.
+    let tileset_tile_size = Vec2::new(
+        tile_tileset.tile_size.0 as f32,
+        tile_tileset.tile_size.1 as f32,
+    );
+    let cell_center = Vec2::new(
+        ctx.tile_size.x * tile_pos.x as f32 + tileset_tile_size.x / 2.,
+        ctx.tile_size.y * (tile_pos.y + 1) as f32 - tileset_tile_size.y / 2.,
+    );
+    let (world_pos_x, world_pos_y) = (cell_center.x, y_sign * cell_center.y);
+
+    let (flip_x, flip_y, quarter_turn) = tile_orientation(flip_h, flip_v, flip_d);
+
+    let tile_aux_info_opt = tile_tileset.tile_stuff.get(&local_tile_id);
+
+    let transform =
+        Transform::from_xyz(world_pos_x, world_pos_y, 0.).with_rotation(if quarter_turn {
+            // A clockwise quarter turn on screen.
+            Quat::from_rotation_z(y_sign * FRAC_PI_2)
+        } else {
+            Quat::IDENTITY
+        });
+
+    let mut tile_entity = world.spawn((tile_pos, TileLayerId(layer_ent)));
+    match settings.chunk_size() {
+        None => {
+            tile_entity.insert((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::WHITE.with_alpha(opacity),
+                        flip_x,
+                        // NOTE:
+                        // With `+ y` down, the sprite must be flipped to read the right way up
+                        // through the camera.
+                        flip_y: flip_y != (settings.y_axis == YAxis::Down),
+                        ..Default::default()
+                    },
+                    transform,
+                    // TODO:
+                    // Don't just get the `0` item
+                    texture: tilemap_textures.get(tileset_index).unwrap().clone(),
+                    ..Default::default()
+                },
+                TextureAtlas {
+                    // TODO:
+                    // Don't just get the `0` item
+                    layout: tilemap_atlases.get(tileset_index).unwrap().clone(),
+                    index: local_tile_id as usize,
+                },
+            ));
+        }
+        // NOTE:
+        // The tile is drawn by its chunk.
+        Some(_) => {
+            tile_entity.insert(SpatialBundle::from_transform(transform));
+        }
+    }
+
+    if let Some(tile_aux_info) = tile_aux_info_opt {
+        insert_class_component(
+            &mut tile_entity,
+            &tile_aux_info.class,
+            &tile_aux_info.properties,
+            ctx.type_registry,
+            ctx.classes,
+        )?;
+
+        #[cfg(feature = "colliders")]
+        if settings.generate_colliders {
+            let (body, defaults) = tile_physics(tile_tileset, local_tile_id, tile_aux_info)?;
+            body.insert(&mut tile_entity)
+                .map_err(|reason| TiledLoaderError::TileProperties {
+                    tileset: tile_tileset.name.clone(),
+                    tile: local_tile_id,
+                    reason,
+                })?;
+
+            // NOTE:
+            // The merged collider can't carry per tile or per shape physics properties.
+            let mergeable_tile = merged_rects.is_some()
+                && body == BodyProperties::default()
+                && defaults == ColliderProperties::default();
+
+            let (mut own, mut merged) = (Vec::new(), Vec::new());
+            for o in &tile_aux_info.objects {
+                let rect = tile_rect(
+                    o,
+                    cell_center,
+                    tileset_tile_size,
+                    flip_x,
+                    flip_y,
+                    quarter_turn,
+                )
+                .filter(|_| {
+                    mergeable_tile
+                        && settings.collider_filter.matches(o, layer_name)
+                        && o.rotation == 0.
+                        && ColliderProperties::read(&o.properties)
+                            == Ok(ColliderProperties::default())
+                });
+
+                match rect {
+                    Some(rect) => merged.push(rect),
+                    None => own.push(o),
+                }
+            }
+
+            if let Some(merged_rects) = merged_rects.filter(|_| !merged.is_empty()) {
+                merged_rects.extend(&merged);
+                tile_entity.insert(MergedTileRects(merged));
+            }
+
+            // NOTE:
+            // Being children of the tile, collision shapes follow its rotation.
+            add_colliders(
+                &mut tile_entity,
+                own,
+                layer_name,
+                defaults,
+                ShapesPlacement {
+                    origin: Vec2::new(-tileset_tile_size.x, -y_sign * tileset_tile_size.y) / 2.,
+                    scale: Vec2::ONE,
+                    tile_size: tileset_tile_size,
+                    flip_x,
+                    flip_y,
+                },
+                settings,
+            )?;
+        }
+    }
+
+    tile_entity.set_parent(layer_ent);
+
+    Ok(tile_entity.id())
+}
+
+/// Sprites can only be flipped along their own axes, so Tiled's diagonal flip (swapping `x` and
+/// `y`, applied before the horizontal and vertical flips) is turned into a clockwise quarter turn
+/// preceded by axis flips.
+///
+/// Returns `(flip_x, flip_y, quarter_turn)`, in Tiled's `+ y` down space.
+fn tile_orientation(flip_h: bool, flip_v: bool, flip_d: bool) -> (bool, bool, bool) {
+    if flip_d {
+        // NOTE:
+        // D = R ∘ V, and the flips that follow it move past R as H ∘ R = R ∘ V and V ∘ R = R ∘ H.
+        (flip_v, !flip_h, true)
+    } else {
+        (flip_h, flip_v, false)
+    }
+}
+
+/// What the spawning functions need to know about the map being loaded.
+pub(crate) struct MapSpawnContext<'a> {
+    // Size of the map's grid cells.
+    pub(crate) tile_size: Vec2,
+    // Already in the map container's space.
+    pub(crate) parallax_origin: Vec2,
+    pub(crate) tile_sets: &'a [TileSet],
+    pub(crate) tilemap_textures: &'a [Handle<bevy::prelude::Image>],
+    pub(crate) tilemap_atlases: &'a [Handle<TextureAtlasLayout>],
+    pub(crate) tilemap_materials: &'a [Handle<ColorMaterial>],
+    #[cfg(feature = "sprite3d")]
+    pub(crate) tilemap_standard_materials: &'a [Handle<bevy::pbr::StandardMaterial>],
+    pub(crate) settings: &'a TiledLoaderSettings,
+    pub(crate) type_registry: &'a TypeRegistry,
+    pub(crate) classes: &'a TiledClassRegistry,
+}
+
+impl<'a> MapSpawnContext<'a> {
+    /// Context of an already loaded map.
+    pub(crate) fn of(
+        map_asset: &'a TiledMapAsset,
+        type_registry: &'a TypeRegistry,
+        classes: &'a TiledClassRegistry,
+    ) -> Self {
+        let TiledMap {
+            tile_size,
+            tile_sets,
+            parallax_origin,
+            ..
+        } = &map_asset.map;
+
+        MapSpawnContext {
+            tile_size: Vec2::new(tile_size.0 as f32, tile_size.1 as f32),
+            parallax_origin: Vec2::new(
+                parallax_origin.0,
+                map_asset.settings.y_sign() * parallax_origin.1,
             ),
+            tile_sets,
+            tilemap_textures: &map_asset.tilemap_textures,
+            tilemap_atlases: &map_asset.tilemap_atlases,
+            tilemap_materials: &map_asset.tilemap_materials,
+            #[cfg(feature = "sprite3d")]
+            tilemap_standard_materials: &map_asset.tilemap_standard_materials,
+            settings: &map_asset.settings,
+            type_registry,
+            classes,
+        }
+    }
+}
+
+/// Finds the tileset a GID belongs to. Returns its index, the tileset, and the tile's local id.
+pub(crate) fn locate_tile(tile_sets: &[TileSet], gid: Gid) -> Option<(usize, &TileSet, u32)> {
+    let tileset = get_tileset_for_gid(tile_sets, gid)?;
+    let index = tile_sets
+        .iter()
+        .position(|ts| ts.first_gid == tileset.first_gid)?;
+
+    Some((index, tileset, get_tile_id(tileset, gid)))
+}
+
+fn spawn_object_layer(
+    world: &mut World,
+    object_layer: &ObjectLayer,
+    z: f32,
+    inherited: Inherited,
+    ctx: &MapSpawnContext,
+) -> Result<Entity, TiledLoaderError> {
+    let Layer {
+        name: layer_name,
+        content,
+        ..
+    } = object_layer;
+    let opacity = inherited.with(object_layer).opacity;
+    let y_sign = ctx.settings.y_sign();
+
+    let mut layer_entity = spawn_layer_entity(world, object_layer, z, inherited, ctx)?;
+
+    let mut result = Ok(());
+
+    layer_entity.with_children(|cb| {
+        result = content.iter().try_for_each(|object| {
+            let Object {
+                id,
+                name,
+                position: (x, y),
+                size,
+                rotation,
+                tile,
+                visible,
+                otype,
+                properties,
+                ..
+            } = object;
+
+            let mut object_entity = cb.spawn((
+                Name::new(if name.is_empty() {
+                    format!("Object {id}")
+                } else {
+                    name.clone()
+                }),
+                TiledObjectId(*id),
+                TiledProperties::from(properties),
+                SpatialBundle {
+                    transform: Transform::from_xyz(*x, y_sign * *y, 0.).with_rotation(
+                        // Tiled rotates clockwise in a `+ y` down space.
+                        Quat::from_rotation_z(y_sign * rotation.to_radians()),
+                    ),
+                    visibility: if *visible {
+                        Visibility::Inherited
+                    } else {
+                        Visibility::Hidden
+                    },
+                    ..Default::default()
+                },
+            ));
+
+            match tile {
+                Some(layer_tile) => insert_tile_object(
+                    &mut object_entity,
+                    object,
+                    layer_tile,
+                    layer_name,
+                    opacity,
+                    ctx,
+                ),
+                None => {
+                    object_entity.insert(object_shape(otype, *size, y_sign));
+                    insert_class_component(
+                        &mut object_entity,
+                        &object.class,
+                        properties,
+                        ctx.type_registry,
+                        ctx.classes,
+                    )?;
+
+                    #[cfg(feature = "colliders")]
+                    if ctx.settings.generate_colliders {
+                        if ctx.settings.collider_filter.matches(object, layer_name) {
+                            add_object_collider(&mut object_entity, object, ctx.settings)?;
+                        }
+
+                        BodyProperties::read(properties)
+                            .and_then(|body| body.insert(&mut object_entity))
+                            .map_err(|reason| TiledLoaderError::Collider {
+                                object_id: *id,
+                                reason,
+                            })?;
+                    }
+
+                    Ok(())
+                }
+            }
+        })
+    });
+
+    result.map(|_| layer_entity.id())
+}
+
+/// Turns an object entity into a tile object : a sprite of the tile, stretched to the object's
+/// size and pivoting around the point given by the tileset's `objectalignment`.
+fn insert_tile_object(
+    object_entity: &mut EntityWorldMut,
+    object: &Object,
+    LayerTile {
+        tile: Gid(tile_gid),
+        flip_h,
+        flip_v,
+        ..
+    }: &LayerTile,
+    layer_name: &str,
+    opacity: f32,
+    ctx: &MapSpawnContext,
+) -> Result<(), TiledLoaderError> {
+    let y_sign = ctx.settings.y_sign();
+
+    let (tileset_index, tileset, local_tile_id) = locate_tile(ctx.tile_sets, Gid(*tile_gid))
+        .ok_or_else(|| TiledLoaderError::MissingTileset {
+            gid: *tile_gid,
+            layer: layer_name.into(),
+        })?;
+
+    let tile_size = Vec2::new(tileset.tile_size.0 as f32, tileset.tile_size.1 as f32);
+    let size = object.size.map_or(tile_size, |(w, h)| Vec2::new(w, h));
+    let pivot = alignment_pivot(tileset.object_alignment);
+
+    match ctx.settings.projection {
+        #[cfg(feature = "sprite3d")]
+        crate::types::MapProjection::Plane3d(_) => spawn_tile_object_quad(
+            object_entity,
+            tileset_index,
+            crate::types::ChunkTile {
+                index: local_tile_id,
+                flip_h: *flip_h,
+                flip_v: *flip_v,
+                flip_d: false,
+                elevation: 0,
+                side_index: local_tile_id,
+            },
+            size,
+            pivot,
+            opacity,
+            ctx,
         ),
-        _ => todo!(),
+        _ => {
+            object_entity.insert((
+                ctx.tilemap_textures[tileset_index].clone(),
+                Sprite {
+                    color: Color::WHITE.with_alpha(opacity),
+                    flip_x: *flip_h,
+                    flip_y: *flip_v != (ctx.settings.y_axis == YAxis::Down),
+                    custom_size: Some(size),
+                    anchor: Anchor::Custom(Vec2::new(pivot.x - 0.5, -y_sign * (0.5 - pivot.y))),
+                    ..Default::default()
+                },
+                TextureAtlas {
+                    layout: ctx.tilemap_atlases[tileset_index].clone(),
+                    index: local_tile_id as usize,
+                },
+            ));
+        }
+    }
+
+    let tile_aux_info = tileset.tile_stuff.get(&local_tile_id);
+
+    // NOTE:
+    // Like in Tiled, tile objects without a class take their tile's, and their properties
+    // override the tile's.
+    let (class, properties) = match tile_aux_info {
+        Some(tile_aux_info) => {
+            let mut properties = tile_aux_info.properties.clone();
+            properties.extend(object.properties.clone());
+
+            let class = if object.class.is_empty() {
+                &tile_aux_info.class
+            } else {
+                &object.class
+            };
+
+            (class, properties)
+        }
+        None => (&object.class, object.properties.clone()),
+    };
+    insert_class_component(
+        object_entity,
+        class,
+        &properties,
+        ctx.type_registry,
+        ctx.classes,
+    )?;
+
+    #[cfg(feature = "colliders")]
+    if ctx.settings.generate_colliders {
+        let (tile_body, tile_defaults) = match tile_aux_info {
+            Some(tile_aux_info) => tile_physics(tileset, local_tile_id, tile_aux_info)?,
+            None => Default::default(),
+        };
+
+        // NOTE:
+        // Tile objects inherit the properties of their tile, unless they override them.
+        let object_error = |reason| TiledLoaderError::Collider {
+            object_id: object.id,
+            reason,
+        };
+        BodyProperties::read(&object.properties)
+            .map_err(object_error)?
+            .or(tile_body)
+            .insert(object_entity);
+        let defaults = ColliderProperties::read(&object.properties)
+            .map_err(object_error)?
+            .or(tile_defaults);
+
+        if let Some(tile_aux_info) = tile_aux_info {
+            // NOTE:
+            // Collision shapes are relative to the tile's top-left corner, in tile pixels.
+            add_colliders(
+                object_entity,
+                &tile_aux_info.objects,
+                layer_name,
+                defaults,
+                ShapesPlacement {
+                    origin: Vec2::new(-pivot.x * size.x, -y_sign * pivot.y * size.y),
+                    scale: size / tile_size,
+                    tile_size,
+                    flip_x: *flip_h,
+                    flip_y: *flip_v,
+                },
+                ctx.settings,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Position of a tile object's pivot inside of it, as a fraction of its size from its top-left
+/// corner (`+ y` down).
+fn alignment_pivot(alignment: ObjectAlignment) -> Vec2 {
+    match alignment {
+        ObjectAlignment::TopLeft => Vec2::new(0., 0.),
+        ObjectAlignment::Top => Vec2::new(0.5, 0.),
+        ObjectAlignment::TopRight => Vec2::new(1., 0.),
+        ObjectAlignment::Left => Vec2::new(0., 0.5),
+        ObjectAlignment::Center => Vec2::new(0.5, 0.5),
+        ObjectAlignment::Right => Vec2::new(1., 0.5),
+        // NOTE:
+        // Only orthogonal maps are supported, for which `Unspecified` means `BottomLeft`.
+        ObjectAlignment::Unspecified | ObjectAlignment::BottomLeft => Vec2::new(0., 1.),
+        ObjectAlignment::Bottom => Vec2::new(0.5, 1.),
+        ObjectAlignment::BottomRight => Vec2::new(1., 1.),
+    }
+}
+
+fn object_shape(otype: &ObjectType, size: Option<PairF32>, y_sign: f32) -> TiledObjectShape {
+    let size = size.map_or(Vec2::ZERO, |(w, h)| Vec2::new(w, h));
+    let points = |ps: &Vec<PairF32>| ps.iter().map(|(x, y)| Vec2::new(*x, y_sign * *y)).collect();
+
+    match otype {
+        ObjectType::Rectangle => TiledObjectShape::Rectangle { size },
+        ObjectType::Ellipse => TiledObjectShape::Ellipse { size },
+        ObjectType::Point => TiledObjectShape::Point,
+        ObjectType::Polygon(ps) => TiledObjectShape::Polygon { points: points(ps) },
+        ObjectType::Polyline(ps) => TiledObjectShape::Polyline { points: points(ps) },
     }
 }
 
-// TODO:
-// I guess for an ObjectLayer ? Maybe ?
-// fn tile_collision(
-//     grid_size: TilemapGridSize,
-//     object_layer: ObjectLayerData,
-//     custom_size: Option<(f32, f32)>,
-// ) -> Option<(Collider, TransformBundle)> {
-//     let (width, height) = custom_size.unwrap_or((grid_size.x, grid_size.y));
-//
-//     let shapes = object_layer
-//         .object_data()
-//         .iter()
-//         .filter_map(|object_data| {
-//             let scale_factor = Vect::new(width / grid_size.x, height / grid_size.y);
-//
-//             let pos = scale_factor * Vect::new(object_data.x, -object_data.y);
-//             let rot = object_data.rotation;
-//
-//             let (shape_pos, collider) = construct_geometry(&object_data.shape, Some(scale_factor));
-//
-//             Some((
-//                 Vect::new(
-//                     pos.x - width / 2. + shape_pos.x,
-//                     pos.y + height / 2. - shape_pos.y,
-//                 ),
-//                 rot,
-//                 collider,
-//             ))
-//         })
-//         .collect::<Vec<_>>();
-//
-//     if shapes.len() == 1 {
-//         let (pos, rot, collider) = shapes[0].clone();
-//
-//         Some((
-//             collider,
-//             TransformBundle::from_transform(Transform {
-//                 translation: Vec3::new(pos.x, pos.y, 0.),
-//                 rotation: Quat::from_rotation_x(rot),
-//                 ..default()
-//             }),
-//         ))
-//     } else if shapes.len() > 1 {
-//         Some((Collider::compound(shapes), TransformBundle::default()))
-//     } else {
-//         None
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_refs_point_at_later_objects() {
+        let mut world = World::new();
+        let lever = world
+            .spawn((
+                TiledObjectId(1),
+                TiledProperties(HashMap::from([
+                    ("opens".to_string(), TiledProperty::Object(2)),
+                    ("unset".to_string(), TiledProperty::Object(0)),
+                    ("label".to_string(), TiledProperty::String("Lever".into())),
+                ])),
+            ))
+            .id();
+        let door = world.spawn(TiledObjectId(2)).id();
+
+        let object_entities = resolve_object_refs(&mut world);
+
+        assert_eq!(object_entities, HashMap::from([(1, lever), (2, door)]));
+        assert_eq!(
+            world
+                .get::<TiledObjectRefs>(lever)
+                .map(|TiledObjectRefs(r)| r),
+            Some(&HashMap::from([("opens".to_string(), door)]))
+        );
+        assert!(world.get::<TiledObjectRefs>(door).is_none());
+    }
+}
@@ -0,0 +1,233 @@
+//! Merging of the axis-aligned collision rectangles of a tile layer, so that a solid area
+//! becomes a few shapes instead of one per tile.
+//!
+//! Rectangles are laid on the grid made by all of their edges, which keeps the merging exact
+//! whatever their sizes.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::math::{Rect, Vec2};
+
+/// Grid cell corner, as `(column, row)`.
+type Corner = (usize, usize);
+
+struct CoverGrid {
+    xs: Vec<f32>,
+    ys: Vec<f32>,
+    /// Covered cells, as `(row, column)` so that rows are contiguous.
+    covered: BTreeSet<(usize, usize)>,
+}
+
+impl CoverGrid {
+    fn new(rects: &[Rect]) -> Self {
+        let rects = rects.iter().filter(|r| !r.is_empty()).collect::<Vec<_>>();
+
+        let edges = |f: fn(&Rect) -> [f32; 2]| {
+            let mut edges = rects.iter().flat_map(|r| f(r)).collect::<Vec<_>>();
+            edges.sort_by(f32::total_cmp);
+            edges.dedup();
+            edges
+        };
+        let xs = edges(|r| [r.min.x, r.max.x]);
+        let ys = edges(|r| [r.min.y, r.max.y]);
+
+        let index = |edges: &[f32], e: f32| {
+            edges
+                .binary_search_by(|p| p.total_cmp(&e))
+                .expect("Edges come from the rectangles")
+        };
+
+        let mut covered = BTreeSet::new();
+        for r in rects {
+            for row in index(&ys, r.min.y)..index(&ys, r.max.y) {
+                for col in index(&xs, r.min.x)..index(&xs, r.max.x) {
+                    covered.insert((row, col));
+                }
+            }
+        }
+
+        Self { xs, ys, covered }
+    }
+
+    fn is_covered(&self, col: Option<usize>, row: Option<usize>) -> bool {
+        row.zip(col).is_some_and(|c| self.covered.contains(&c))
+    }
+
+    fn point(&self, (col, row): Corner) -> Vec2 {
+        Vec2::new(self.xs[col], self.ys[row])
+    }
+
+    /// Spans of consecutive covered cells of a row, as `[first column, last column + 1)`.
+    fn runs(&self, row: usize) -> Vec<(usize, usize)> {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+
+        for &(_, col) in self.covered.range((row, 0)..(row + 1, 0)) {
+            match runs.last_mut() {
+                Some((_, end)) if *end == col => *end += 1,
+                _ => runs.push((col, col + 1)),
+            }
+        }
+
+        runs
+    }
+}
+
+/// Covers the union of `rects` with fewer rectangles, by joining cells into horizontal runs and
+/// then stacking runs of consecutive rows that span the same columns.
+pub(crate) fn merge_rects(rects: &[Rect]) -> Vec<Rect> {
+    let grid = CoverGrid::new(rects);
+    let rows = grid.ys.len().saturating_sub(1);
+
+    let mut merged = Vec::new();
+    // Runs of the previous row, and the row they started at.
+    let mut open: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+
+    for row in 0..=rows {
+        let runs = if row < rows {
+            grid.runs(row)
+        } else {
+            Vec::new()
+        };
+
+        open.retain(|&(start, end), &mut first_row| {
+            let continued = runs.contains(&(start, end));
+            if !continued {
+                merged.push(Rect::from_corners(
+                    grid.point((start, first_row)),
+                    grid.point((end, row)),
+                ));
+            }
+            continued
+        });
+
+        for run in runs {
+            open.entry(run).or_insert(row);
+        }
+    }
+
+    merged
+}
+
+/// Traces the boundary of the union of `rects`, as closed loops of points. Holes get their own
+/// loops.
+pub(crate) fn trace_outlines(rects: &[Rect]) -> Vec<Vec<Vec2>> {
+    let grid = CoverGrid::new(rects);
+
+    // NOTE:
+    // Every boundary edge of a cell is walked with the cell on the same side, so each corner has
+    // as many boundary edges leaving it as reaching it, and walking them always closes a loop.
+    let mut edges: BTreeMap<Corner, Vec<Corner>> = BTreeMap::new();
+    for &(row, col) in &grid.covered {
+        let mut edge = |from: Corner, to: Corner| edges.entry(from).or_default().push(to);
+
+        if !grid.is_covered(Some(col), row.checked_sub(1)) {
+            edge((col, row), (col + 1, row));
+        }
+        if !grid.is_covered(Some(col + 1), Some(row)) {
+            edge((col + 1, row), (col + 1, row + 1));
+        }
+        if !grid.is_covered(Some(col), Some(row + 1)) {
+            edge((col + 1, row + 1), (col, row + 1));
+        }
+        if !grid.is_covered(col.checked_sub(1), Some(row)) {
+            edge((col, row + 1), (col, row));
+        }
+    }
+
+    let mut outlines = Vec::new();
+
+    while let Some(start) = edges
+        .iter()
+        .find_map(|(corner, to)| (!to.is_empty()).then_some(*corner))
+    {
+        let mut corners = vec![start];
+        let mut current = start;
+
+        loop {
+            current = edges
+                .get_mut(&current)
+                .and_then(Vec::pop)
+                .expect("Boundary edges form closed loops");
+            if current == start {
+                break;
+            }
+            corners.push(current);
+        }
+
+        // NOTE:
+        // Only the corners where the outline turns are kept.
+        let turns = (0..corners.len())
+            .filter(|&i| {
+                let prev = corners[(i + corners.len() - 1) % corners.len()];
+                let next = corners[(i + 1) % corners.len()];
+                !(prev.0 == next.0 || prev.1 == next.1)
+            })
+            .map(|i| grid.point(corners[i]))
+            .collect();
+
+        outlines.push(turns);
+    }
+
+    outlines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(x: f32, y: f32) -> Rect {
+        Rect::new(x * 16., y * 16., (x + 1.) * 16., (y + 1.) * 16.)
+    }
+
+    fn area(rects: &[Rect]) -> f32 {
+        rects.iter().map(|r| r.width() * r.height()).sum()
+    }
+
+    #[test]
+    fn solid_block_is_one_rect() {
+        let tiles = (0..4)
+            .flat_map(|x| (0..3).map(move |y| tile(x as f32, y as f32)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(merge_rects(&tiles), vec![Rect::new(0., 0., 64., 48.)]);
+    }
+
+    #[test]
+    fn l_shape_keeps_its_area() {
+        let tiles = [tile(0., 0.), tile(0., 1.), tile(1., 1.), tile(2., 1.)];
+        let merged = merge_rects(&tiles);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(area(&merged), area(&tiles));
+    }
+
+    #[test]
+    fn partial_rects_merge() {
+        // Half height platforms next to each other.
+        let rects = [Rect::new(0., 8., 16., 16.), Rect::new(16., 8., 32., 16.)];
+
+        assert_eq!(merge_rects(&rects), vec![Rect::new(0., 8., 32., 16.)]);
+    }
+
+    #[test]
+    fn ring_has_two_outlines() {
+        let tiles = (0..3)
+            .flat_map(|x| (0..3).map(move |y| (x, y)))
+            .filter(|&p| p != (1, 1))
+            .map(|(x, y)| tile(x as f32, y as f32))
+            .collect::<Vec<_>>();
+        let outlines = trace_outlines(&tiles);
+
+        assert_eq!(outlines.len(), 2);
+        assert!(outlines.iter().all(|o| o.len() == 4));
+    }
+
+    #[test]
+    fn outline_follows_l_shape() {
+        let tiles = [tile(0., 0.), tile(0., 1.), tile(1., 1.)];
+        let outlines = trace_outlines(&tiles);
+
+        assert_eq!(outlines.len(), 1);
+        assert_eq!(outlines[0].len(), 6);
+    }
+}
@@ -0,0 +1,198 @@
+//! Maps laid out on the `XZ` plane, for [`MapProjection::Plane3d`].
+//!
+//! Everything below the map container stays in the same space as with 2d maps. The container is
+//! rotated so that this space's `XY` plane becomes the `XZ` plane, and its `+ z` points up.
+//!
+//! [`MapProjection::Plane3d`]: crate::types::MapProjection::Plane3d
+
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::pbr::{PbrBundle, StandardMaterial};
+use bevy::prelude::*;
+use tiled_parse::data_types::{Properties, TileAuxInfo, TiledPropertyType};
+
+use crate::load::MapSpawnContext;
+use crate::types::{
+    ChunkTile, Extrusion, Plane3dSettings, TileChunk, TilePos, TiledLoaderSettings,
+};
+
+/// Rotation of the map container. With [`YAxis::Up`](crate::types::YAxis::Up), Tiled's `+ y`
+/// ends up along `+ z`.
+pub(crate) fn map_rotation() -> Quat {
+    Quat::from_rotation_x(-FRAC_PI_2)
+}
+
+/// Material of a tileset's image. Extruded tiles are cut out instead of blended, so that blocks
+/// hide each other.
+pub(crate) fn tileset_material(texture: Handle<Image>, extruded: bool) -> StandardMaterial {
+    StandardMaterial {
+        base_color_texture: Some(texture),
+        // NOTE:
+        // Blending keeps the transparent parts of tiles and the opacity of layers, but doesn't
+        // write depth.
+        alpha_mode: if extruded {
+            AlphaMode::Mask(0.5)
+        } else {
+            AlphaMode::Blend
+        },
+        // NOTE:
+        // Windings depend on the direction of `+ y`, so both sides of faces are drawn, lit along
+        // the normals of the mesh.
+        cull_mode: None,
+        perceptual_roughness: 1.,
+        ..Default::default()
+    }
+}
+
+/// Height of a layer above its parent, from its height property.
+pub(crate) fn layer_height(properties: &Properties, plane3d: &Plane3dSettings) -> Option<f32> {
+    match properties.get(&plane3d.height_property)? {
+        TiledPropertyType::Float(height) => Some(*height),
+        TiledPropertyType::Int(height) => Some(*height as f32),
+        _ => None,
+    }
+}
+
+pub(crate) fn extrusion(settings: &TiledLoaderSettings) -> Option<&Extrusion> {
+    settings.plane3d()?.extrusion.as_ref()
+}
+
+/// Elevation of a tile and the index of the tile drawn on its sides, from its properties. Tiles
+/// are flat unless the map is extruded.
+pub(crate) fn tile_elevation(
+    tile_aux_info: Option<&TileAuxInfo>,
+    index: u32,
+    settings: &TiledLoaderSettings,
+) -> (u32, u32) {
+    let (Some(extrusion), Some(tile_aux_info)) = (extrusion(settings), tile_aux_info) else {
+        return (0, index);
+    };
+    let int = |name: &str| match tile_aux_info.properties.get(name) {
+        Some(TiledPropertyType::Int(v)) => u32::try_from(*v).ok(),
+        _ => None,
+    };
+
+    (
+        int(&extrusion.elevation_property).unwrap_or(0),
+        int(&extrusion.side_property).unwrap_or(index),
+    )
+}
+
+/// Marks an extruded [`TileChunk`] that gets a trimesh collider matching its mesh.
+#[cfg(feature = "rapier3d_colliders")]
+#[derive(Component, Reflect, Clone, Copy, Debug, Default)]
+pub struct ChunkTrimeshCollider;
+
+/// Rebuilds the colliders of the [`ChunkTrimeshCollider`] chunks that changed.
+#[cfg(feature = "rapier3d_colliders")]
+pub(crate) fn update_chunk_colliders(
+    chunks: Query<(Entity, &TileChunk), (With<ChunkTrimeshCollider>, Changed<TileChunk>)>,
+    layouts: Res<Assets<TextureAtlasLayout>>,
+    mut commands: Commands,
+) {
+    use bevy::render::mesh::VertexAttributeValues;
+    use bevy_rapier3d::prelude::Collider;
+
+    use crate::chunks::chunk_mesh;
+
+    chunks.iter().for_each(|(e, chunk)| {
+        let Some(layout) = layouts.get(&chunk.layout) else {
+            warn!("The atlas of a tile chunk isn't loaded, its collider can't be built");
+            return;
+        };
+
+        let mesh = chunk_mesh(chunk, layout);
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+        let indices = mesh
+            .indices()
+            .map(|indices| indices.iter().map(|i| i as u32).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        // NOTE:
+        // Trimeshes need at least one triangle.
+        if indices.is_empty() {
+            commands.entity(e).remove::<Collider>();
+            return;
+        }
+
+        commands.entity(e).insert(Collider::trimesh(
+            positions.iter().map(|p| Vec3::from_array(*p)).collect(),
+            indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect(),
+        ));
+    });
+}
+
+/// Draws a tile object with a quad of its tile, stretched to `size` and pivoting around `pivot`
+/// (a fraction of its size from its top-left corner), as a child of the object's entity.
+pub(crate) fn spawn_tile_object_quad(
+    object_entity: &mut EntityWorldMut,
+    tileset_index: usize,
+    tile: ChunkTile,
+    size: Vec2,
+    pivot: Vec2,
+    opacity: f32,
+    ctx: &MapSpawnContext,
+) {
+    let y_sign = ctx.settings.y_sign();
+
+    // NOTE:
+    // A chunk of a single cell the size of the object, so that its mesh is built like the ones
+    // of tile layers.
+    let chunk = TileChunk {
+        origin: TilePos::new(0, 0),
+        size: UVec2::ONE,
+        tiles: vec![Some(tile)],
+        layout: ctx.tilemap_atlases[tileset_index].clone(),
+        cell_size: size,
+        tile_size: size,
+        y_sign,
+        opacity,
+        step_height: 0.,
+    };
+
+    object_entity.with_children(|cb| {
+        cb.spawn((
+            Name::new("Tile"),
+            chunk,
+            PbrBundle {
+                material: ctx.tilemap_standard_materials[tileset_index].clone(),
+                transform: Transform::from_xyz(-pivot.x * size.x, -y_sign * pivot.y * size.y, 0.),
+                ..Default::default()
+            },
+        ));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_is_laid_on_the_xz_plane() {
+        let rotation = map_rotation();
+
+        // Up in the map container is up in the world, and Tiled's down points at the camera.
+        assert!(rotation.mul_vec3(Vec3::Z).abs_diff_eq(Vec3::Y, 1e-6));
+        assert!(rotation.mul_vec3(Vec3::NEG_Y).abs_diff_eq(Vec3::Z, 1e-6));
+    }
+
+    #[test]
+    fn heights_are_numbers() {
+        let plane3d = Plane3dSettings::default();
+        let height = |property| {
+            let properties = [("height".to_string(), property)].into_iter().collect();
+            layer_height(&properties, &plane3d)
+        };
+
+        assert_eq!(height(TiledPropertyType::Float(2.5)), Some(2.5));
+        assert_eq!(height(TiledPropertyType::Int(3)), Some(3.));
+        assert_eq!(height(TiledPropertyType::Bool(true)), None);
+    }
+}
@@ -1,28 +1,242 @@
-use crate::{load::TiledLoader, relations::deserialize_rapier_collider, types::*};
+use crate::{
+    classes::TiledClassRegistry,
+    load::TiledLoader,
+    systems::{
+        handle_parallax, propagate_layer_opacity, run_object_spawners, spawn_map_scenes,
+        sync_tile_storage, update_tile_chunk_meshes,
+    },
+    types::*,
+};
+use bevy::app::SpawnScene;
+use bevy::ecs::reflect::ReflectMapEntities;
 use bevy::prelude::*;
+use bevy::reflect::GetTypeRegistration;
+use bevy::scene::scene_spawner_system;
+use bevy::sprite::Mesh2dHandle;
+use serde::de::DeserializeOwned;
+
+/// Registration of the component types that the loader (or anything else) can store in map
+/// scenes as [`Serialized`] components.
+pub trait SerializedComponentAppExt {
+    fn register_serialized_component<T: Component + DeserializeOwned>(&mut self) -> &mut Self;
+}
+
+impl SerializedComponentAppExt for App {
+    fn register_serialized_component<T: Component + DeserializeOwned>(&mut self) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_insert_with(SerializedComponentRegistry::default)
+            .register::<T>();
+        self
+    }
+}
+
+/// Registration of the components that objects, tiles and layers of a Tiled class are given.
+pub trait TiledClassAppExt {
+    fn register_tiled_class<T: Component + Reflect + GetTypeRegistration>(
+        &mut self,
+        class: impl Into<String>,
+    ) -> &mut Self;
+}
+
+impl TiledClassAppExt for App {
+    fn register_tiled_class<T: Component + Reflect + GetTypeRegistration>(
+        &mut self,
+        class: impl Into<String>,
+    ) -> &mut Self {
+        self.register_type::<T>();
+        self.world_mut()
+            .get_resource_or_insert_with(TiledClassRegistry::default)
+            .register::<T>(class);
+        self
+    }
+}
+
+/// Registration of setup code for objects, run once the scene of their map is instantiated under
+/// an entity holding the map's handle (see [`TiledMapBundle`]).
+pub trait ObjectSpawnerAppExt {
+    fn register_object_spawner(
+        &mut self,
+        key: ObjectSpawnerKey,
+        spawner: ObjectSpawner,
+    ) -> &mut Self;
+}
+
+impl ObjectSpawnerAppExt for App {
+    fn register_object_spawner(
+        &mut self,
+        key: ObjectSpawnerKey,
+        spawner: ObjectSpawner,
+    ) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_insert_with(ObjectSpawnerRegistry::default)
+            .register(key, spawner);
+        self
+    }
+}
 
 pub fn tiled_scene_plugin(app: &mut App) {
     app.register_type::<TiledMapContainer>()
+        .register_type::<TiledLayerId>()
+        .register_type::<LayerOpacity>()
+        .register_type::<LayerParallax>()
+        .register_type::<TiledObjectId>()
+        .register_type::<TiledObjectShape>()
+        .register_type::<TiledProperties>()
+        .register_type::<TilePos>()
+        .register_type::<TileLayerId>()
+        .register_type::<TileStorage>()
+        .register_type::<TileChunk>()
+        .register_type::<ChunkTile>()
+        .register_type::<Handle<ColorMaterial>>()
+        .register_type::<TiledObjectEntities>()
+        .register_type::<TiledObjectRefs>()
         .register_type::<Serialized>()
+        .register_type::<SerializedComponent>()
         .register_type_data::<TextureAtlas, ReflectComponent>()
         .register_type_data::<TiledMapContainer, ReflectComponent>()
+        .register_type_data::<TiledLayerId, ReflectComponent>()
+        .register_type_data::<LayerOpacity, ReflectComponent>()
+        .register_type_data::<LayerParallax, ReflectComponent>()
+        .register_type_data::<TiledObjectId, ReflectComponent>()
+        .register_type_data::<TiledObjectShape, ReflectComponent>()
+        .register_type_data::<TiledProperties, ReflectComponent>()
+        .register_type_data::<TilePos, ReflectComponent>()
+        .register_type_data::<TileLayerId, ReflectComponent>()
+        .register_type_data::<TileLayerId, ReflectMapEntities>()
+        .register_type_data::<TileStorage, ReflectComponent>()
+        .register_type_data::<TileStorage, ReflectMapEntities>()
+        .register_type_data::<TileChunk, ReflectComponent>()
+        .register_type_data::<Handle<ColorMaterial>, ReflectComponent>()
+        .register_type_data::<Mesh2dHandle, ReflectComponent>()
+        .register_type_data::<TiledObjectEntities, ReflectComponent>()
+        .register_type_data::<TiledObjectEntities, ReflectMapEntities>()
+        .register_type_data::<TiledObjectRefs, ReflectComponent>()
+        .register_type_data::<TiledObjectRefs, ReflectMapEntities>()
         .register_type_data::<Serialized, ReflectComponent>()
+        .init_resource::<SerializedComponentRegistry>()
+        .init_resource::<TiledClassRegistry>()
+        .init_resource::<ObjectSpawnerRegistry>()
         .init_asset::<TiledMapAsset>()
         .init_asset_loader::<TiledLoader>()
+        .add_systems(Update, spawn_map_scenes)
+        .add_systems(SpawnScene, run_object_spawners.after(scene_spawner_system))
+        .add_systems(
+            PostUpdate,
+            (
+                propagate_layer_opacity,
+                update_tile_chunk_meshes.after(propagate_layer_opacity),
+                sync_tile_storage,
+            ),
+        )
         .observe(
-            |trigger: Trigger<OnAdd, Serialized>, query: Query<&Serialized>, mut c: Commands| {
-                let Ok(Serialized { data, thingy }) = query.get(trigger.entity()) else {
+            |trigger: Trigger<OnAdd, Serialized>,
+             query: Query<&Serialized>,
+             registry: Res<SerializedComponentRegistry>,
+             mut c: Commands| {
+                let Ok(Serialized { components }) = query.get(trigger.entity()) else {
                     return;
                 };
 
                 let mut ec = c.entity(trigger.entity());
                 ec.remove::<Serialized>();
 
-                ec.insert(match thingy {
-                    SceneSerializedComponents::RCollider => {
-                        deserialize_rapier_collider(&data).unwrap()
+                for component in components {
+                    match registry.insert(component, &mut ec) {
+                        Some(Ok(())) => {}
+                        Some(Err(e)) => {
+                            error!("Could not deserialize `{}`: {e}", component.type_name)
+                        }
+                        None => warn!(
+                            "`{}` was not registered with `register_serialized_component`",
+                            component.type_name
+                        ),
                     }
-                });
+                }
             },
         );
+
+    #[cfg(feature = "colliders")]
+    app.register_type::<crate::colliders::MergedTileRects>()
+        .register_type_data::<crate::colliders::MergedTileRects, ReflectComponent>();
+    // NOTE:
+    // Colliders, and the physics components read from Tiled properties, are stored in the scene as
+    // `Serialized` components.
+    #[cfg(feature = "rapier2d_colliders")]
+    {
+        use bevy_rapier2d::prelude::{
+            AdditionalMassProperties, Collider, CollisionGroups, Friction, Restitution, RigidBody,
+            Sensor, SolverGroups,
+        };
+
+        app.register_serialized_component::<Collider>()
+            .register_serialized_component::<RigidBody>()
+            .register_serialized_component::<AdditionalMassProperties>()
+            .register_serialized_component::<Sensor>()
+            .register_serialized_component::<Friction>()
+            .register_serialized_component::<Restitution>()
+            .register_serialized_component::<CollisionGroups>()
+            .register_serialized_component::<SolverGroups>();
+    }
+    #[cfg(feature = "avian2d_colliders")]
+    {
+        use avian2d::prelude::{
+            Collider, CollisionLayers, Friction, Mass, Restitution, RigidBody, Sensor,
+        };
+
+        app.register_serialized_component::<Collider>()
+            .register_serialized_component::<RigidBody>()
+            .register_serialized_component::<Mass>()
+            .register_serialized_component::<Sensor>()
+            .register_serialized_component::<Friction>()
+            .register_serialized_component::<Restitution>()
+            .register_serialized_component::<CollisionLayers>();
+    }
+    #[cfg(feature = "rapier3d_colliders")]
+    {
+        use bevy_rapier3d::prelude::{
+            AdditionalMassProperties, Collider, CollisionGroups, Friction, Restitution, RigidBody,
+            Sensor, SolverGroups,
+        };
+
+        app.register_serialized_component::<Collider>()
+            .register_serialized_component::<RigidBody>()
+            .register_serialized_component::<AdditionalMassProperties>()
+            .register_serialized_component::<Sensor>()
+            .register_serialized_component::<Friction>()
+            .register_serialized_component::<Restitution>()
+            .register_serialized_component::<CollisionGroups>()
+            .register_serialized_component::<SolverGroups>();
+    }
+    #[cfg(feature = "rapier3d_colliders")]
+    app.register_type::<crate::plane3d::ChunkTrimeshCollider>()
+        .register_type_data::<crate::plane3d::ChunkTrimeshCollider, ReflectComponent>()
+        .add_systems(
+            PostUpdate,
+            crate::plane3d::update_chunk_colliders.after(propagate_layer_opacity),
+        );
+
+    #[cfg(feature = "sprite3d")]
+    app.register_type_data::<Handle<Mesh>, ReflectComponent>()
+        .register_type_data::<Handle<bevy::pbr::StandardMaterial>, ReflectComponent>();
+
+    // NOTE:
+    // Drawing the tilemaps is left to `bevy_ecs_tilemap::TilemapPlugin`, which must be added too.
+    #[cfg(feature = "ecs_tilemap")]
+    app.register_type::<crate::ecs_tilemap::EcsTilemapChunk>()
+        .register_type_data::<crate::ecs_tilemap::EcsTilemapChunk, ReflectComponent>()
+        .add_systems(
+            PostUpdate,
+            crate::ecs_tilemap::sync_ecs_tilemaps.after(propagate_layer_opacity),
+        );
+}
+
+/// Opt-in parallax scrolling of the layers of spawned maps, relative to the camera marked with
+/// [`ParallaxCamera`].
+pub fn tiled_parallax_plugin(app: &mut App) {
+    app.register_type::<ParallaxCamera>()
+        .register_type_data::<ParallaxCamera, ReflectComponent>()
+        .add_systems(
+            PostUpdate,
+            handle_parallax.before(TransformSystem::TransformPropagate),
+        );
 }
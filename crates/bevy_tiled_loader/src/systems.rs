@@ -0,0 +1,554 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::scene::SceneInstanceReady;
+use bevy::sprite::Mesh2dHandle;
+use bevy::utils::hashbrown::{HashMap, HashSet};
+use tiled_parse::data_types::{LayerTile, Object, TileSet, TiledLayer, TiledMap};
+use tiled_parse::relations::{get_tile_id, get_tileset_for_gid};
+
+use crate::chunks::chunk_mesh;
+use crate::types::{
+    ChunkTile, LayerOpacity, LayerParallax, ObjectSpawnContext, ObjectSpawnerRegistry,
+    ParallaxCamera, TileChunk, TileLayerId, TilePos, TileStorage, TiledLayerId, TiledMapAsset,
+    TiledMapContainer, TiledObjectId,
+};
+
+/// Instantiates the scene of maps spawned with a [`TiledMapBundle`](crate::types::TiledMapBundle),
+/// once they are loaded.
+pub fn spawn_map_scenes(
+    maps: Query<(Entity, &Handle<TiledMapAsset>), Without<Handle<Scene>>>,
+    map_assets: Res<Assets<TiledMapAsset>>,
+    mut commands: Commands,
+) {
+    maps.iter().for_each(|(e, map)| {
+        if let Some(map_asset) = map_assets.get(map) {
+            commands.entity(e).insert(map_asset.scene.clone());
+        }
+    });
+}
+
+/// Runs the [`ObjectSpawner`](crate::types::ObjectSpawner)s of the objects of a map, when its
+/// scene is instantiated under an entity holding the map's handle.
+pub fn run_object_spawners(
+    mut ready: EventReader<SceneInstanceReady>,
+    maps: Query<&Handle<TiledMapAsset>>,
+    map_assets: Res<Assets<TiledMapAsset>>,
+    registry: Res<ObjectSpawnerRegistry>,
+    children: Query<&Children>,
+    layer_ids: Query<&TiledLayerId>,
+    object_ids: Query<&TiledObjectId>,
+    mut commands: Commands,
+) {
+    ready.read().for_each(|SceneInstanceReady { parent }| {
+        let Some((map, map_asset)) = maps
+            .get(*parent)
+            .ok()
+            .and_then(|map| map_assets.get(map).map(|map_asset| (map, map_asset)))
+        else {
+            return;
+        };
+        let TiledMap {
+            layers, tile_sets, ..
+        } = &map_asset.map;
+
+        let object_layers = layers
+            .iter()
+            .filter_map(|layer| match layer {
+                TiledLayer::Object(object_layer) => Some((object_layer.id, object_layer)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        children.iter_descendants(*parent).for_each(|layer_entity| {
+            let Some(layer) = layer_ids
+                .get(layer_entity)
+                .ok()
+                .and_then(|TiledLayerId(id)| object_layers.get(id))
+            else {
+                return;
+            };
+            let ctx = ObjectSpawnContext {
+                map,
+                layer,
+                layer_entity,
+            };
+
+            let objects = layer
+                .content
+                .iter()
+                .map(|o| (o.id, o))
+                .collect::<HashMap<_, _>>();
+
+            // NOTE:
+            // Object entities are direct children of their layer's.
+            children
+                .get(layer_entity)
+                .into_iter()
+                .flatten()
+                .for_each(|&e| {
+                    let Some(object) = object_ids
+                        .get(e)
+                        .ok()
+                        .and_then(|TiledObjectId(id)| objects.get(id))
+                    else {
+                        return;
+                    };
+
+                    let mut entity_commands = commands.entity(e);
+                    registry
+                        .get(object_class(object, tile_sets), &object.name)
+                        .for_each(|spawner| spawner(object, &ctx, &mut entity_commands));
+                });
+        });
+    });
+}
+
+/// Class of an object, which tile objects without one take from their tile.
+fn object_class<'a>(object: &'a Object, tile_sets: &'a [TileSet]) -> &'a str {
+    match object.tile {
+        Some(LayerTile { tile, .. }) if object.class.is_empty() => {
+            get_tileset_for_gid(tile_sets, tile)
+                .and_then(|ts| ts.tile_stuff.get(&get_tile_id(ts, tile)))
+                .map_or("", |tile_aux_info| &tile_aux_info.class)
+        }
+        _ => &object.class,
+    }
+}
+
+/// A tile of a tile layer, found by [`LayerTiles`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerTileRef {
+    /// A tile with its own entity, stored in the layer's [`TileStorage`].
+    Entity(Entity),
+    /// A tile only drawn by a [`TileChunk`] of the layer.
+    Chunk { chunk: Entity, tile: ChunkTile },
+}
+
+/// Looks up the tiles of tile layers, including the ones drawn by chunks that have no entity.
+#[derive(SystemParam)]
+pub struct LayerTiles<'w, 's> {
+    storages: Query<'w, 's, &'static TileStorage>,
+    children: Query<'w, 's, &'static Children>,
+    chunks: Query<'w, 's, &'static TileChunk>,
+}
+
+impl LayerTiles<'_, '_> {
+    /// The tile at `pos` of `layer`, preferring its entity when it has one.
+    pub fn get(&self, layer: Entity, pos: TilePos) -> Option<LayerTileRef> {
+        if let Some(tile) = self.storages.get(layer).ok().and_then(|s| s.get(pos)) {
+            return Some(LayerTileRef::Entity(tile));
+        }
+
+        self.children.get(layer).ok()?.iter().find_map(|&chunk| {
+            let tile = self.chunks.get(chunk).ok()?.get(pos)?;
+            Some(LayerTileRef::Chunk { chunk, tile })
+        })
+    }
+}
+
+/// Keeps the [`TileStorage`] of tile layers in line with the tiles added, moved or removed at
+/// runtime.
+///
+/// Every storage is searched for the previous position of moved and removed tiles, which only
+/// happens on frames where there are some.
+pub fn sync_tile_storage(
+    tiles: Query<(Entity, &TilePos, &TileLayerId), Or<(Changed<TilePos>, Changed<TileLayerId>)>>,
+    mut removed: RemovedComponents<TilePos>,
+    mut storages: Query<&mut TileStorage>,
+) {
+    // NOTE:
+    // Tiles of freshly instantiated maps are already stored where they are.
+    let moved = tiles
+        .iter()
+        .filter(|(e, pos, TileLayerId(layer))| {
+            storages
+                .get(*layer)
+                .map_or(true, |storage| storage.get(**pos) != Some(*e))
+        })
+        .collect::<Vec<_>>();
+
+    let stale = removed
+        .read()
+        .chain(moved.iter().map(|(e, ..)| *e))
+        .collect::<HashSet<_>>();
+    if stale.is_empty() {
+        return;
+    }
+
+    storages.iter_mut().for_each(|mut storage| {
+        let positions = storage
+            .iter()
+            .filter(|(_, e)| stale.contains(e))
+            .map(|(pos, _)| pos)
+            .collect::<Vec<_>>();
+
+        positions.into_iter().for_each(|pos| {
+            storage.remove(pos);
+        });
+    });
+
+    moved.into_iter().for_each(
+        |(e, pos, TileLayerId(layer))| match storages.get_mut(*layer) {
+            Ok(mut storage) if storage.in_bounds(*pos) => {
+                storage.set(*pos, e);
+            }
+            Ok(_) => warn!("Tile {e} is outside of the grid of its layer, at {pos:?}"),
+            Err(_) => warn!("Tile {e} points at {layer}, which isn't a tile layer"),
+        },
+    );
+}
+
+/// Keeps the alpha of sprites and tile chunks in line with the [`LayerOpacity`] of every layer
+/// above them, so fading a layer (or a group) at runtime fades everything it contains.
+pub fn propagate_layer_opacity(
+    changed_layers: Query<Entity, Changed<LayerOpacity>>,
+    layers: Query<&LayerOpacity>,
+    parents: Query<&Parent>,
+    children: Query<&Children>,
+    mut drawn: Query<AnyOf<(&mut Sprite, &mut TileChunk)>>,
+) {
+    changed_layers.iter().for_each(|layer| {
+        let inherited_opacity = parents
+            .iter_ancestors(layer)
+            .filter_map(|ancestor| layers.get(ancestor).ok())
+            .map(|LayerOpacity(o)| *o)
+            .product();
+
+        apply_opacity(layer, inherited_opacity, &layers, &children, &mut drawn);
+    });
+}
+
+fn apply_opacity(
+    e: Entity,
+    inherited_opacity: f32,
+    layers: &Query<&LayerOpacity>,
+    children: &Query<&Children>,
+    drawn: &mut Query<AnyOf<(&mut Sprite, &mut TileChunk)>>,
+) {
+    let opacity = inherited_opacity * layers.get(e).map_or(1., |LayerOpacity(o)| *o);
+
+    if let Ok((sprite, chunk)) = drawn.get_mut(e) {
+        if let Some(mut sprite) = sprite {
+            sprite.color.set_alpha(opacity);
+        }
+        // NOTE:
+        // Changing a chunk rebuilds its mesh.
+        if let Some(mut chunk) = chunk.filter(|chunk| chunk.opacity != opacity) {
+            chunk.opacity = opacity;
+        }
+    }
+
+    if let Ok(cs) = children.get(e) {
+        cs.iter()
+            .for_each(|c| apply_opacity(*c, opacity, layers, children, drawn));
+    }
+}
+
+/// Rebuilds the meshes of the [`TileChunk`]s that changed, including the ones just spawned, and
+/// builds the ones that waited for their atlas to load. Both 2d and 3d meshes are handled.
+pub fn update_tile_chunk_meshes(
+    mut chunks: Query<(
+        Ref<TileChunk>,
+        AnyOf<(&mut Mesh2dHandle, &mut Handle<Mesh>)>,
+    )>,
+    layouts: Res<Assets<TextureAtlasLayout>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    chunks.iter_mut().for_each(|(chunk, (mesh_2d, mesh_3d))| {
+        let Some(mesh_id) = mesh_2d
+            .as_deref()
+            .map(|m| m.0.id())
+            .or(mesh_3d.as_deref().map(Handle::id))
+        else {
+            return;
+        };
+
+        // NOTE:
+        // Chunks without a mesh are retried until their atlas is loaded, even if they didn't
+        // change since.
+        if !chunk.is_changed() && meshes.contains(mesh_id) {
+            return;
+        }
+
+        let Some(layout) = layouts.get(&chunk.layout) else {
+            if chunk.is_changed() {
+                warn!("The atlas of a tile chunk isn't loaded, its mesh can't be built yet");
+            }
+            return;
+        };
+
+        let mesh = chunk_mesh(&chunk, layout);
+        match meshes.get_mut(mesh_id) {
+            Some(existing) => *existing = mesh,
+            None => {
+                let handle = meshes.add(mesh);
+                if let Some(mut mesh_2d) = mesh_2d {
+                    mesh_2d.0 = handle;
+                } else if let Some(mut mesh_3d) = mesh_3d {
+                    *mesh_3d = handle;
+                }
+            }
+        }
+    });
+}
+
+/// Offsets parallax layers the way Tiled's preview does : by the distance between the camera and
+/// the map's parallax origin, scaled by `1 - factor`.
+pub fn handle_parallax(
+    camera_trans_q: Query<&Transform, With<ParallaxCamera>>,
+    containers: Query<&GlobalTransform, With<TiledMapContainer>>,
+    parents: Query<&Parent>,
+    mut parallax_layer: Query<(Entity, &mut Transform, &LayerParallax), Without<ParallaxCamera>>,
+) {
+    let Ok(cam_transform) = camera_trans_q.get_single() else {
+        return;
+    };
+
+    parallax_layer
+        .iter_mut()
+        .for_each(|(layer, mut layer_transform, layer_parallax)| {
+            let Some(container_transform) = parents
+                .iter_ancestors(layer)
+                .find_map(|ancestor| containers.get(ancestor).ok())
+            else {
+                return;
+            };
+
+            // NOTE:
+            // The camera's position in the map's pixel space, where the parallax origin is.
+            let cam_position = container_transform
+                .affine()
+                .inverse()
+                .transform_point3(cam_transform.translation)
+                .truncate();
+
+            // NOTE:
+            // The parent groups already moved the layer by their own factor, so only the
+            // difference is applied here.
+            let offset = (cam_position - layer_parallax.origin)
+                * (layer_parallax.parent_factor - layer_parallax.factor);
+
+            layer_transform.translation = layer_parallax.base_translation + offset.extend(0.);
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use tiled_parse::data_types::{TileAuxInfo, TiledPropertyType};
+
+    use crate::test_util::{layer, layer_tile, map, object, properties, tile_set};
+    use crate::types::ObjectSpawnerKey;
+
+    /// Map and layer entity the spawner of an object was given, once per run.
+    #[derive(Component, Default)]
+    struct SpawnedWith(Vec<(Handle<TiledMapAsset>, Entity)>);
+
+    fn record_context(_: &Object, ctx: &ObjectSpawnContext, e: &mut EntityCommands) {
+        let context = (ctx.map.clone(), ctx.layer_entity);
+        e.add(move |mut e: EntityWorldMut| e.get_mut::<SpawnedWith>().unwrap().0.push(context));
+    }
+
+    #[test]
+    fn object_spawners_run_once_their_scene_is_ready() {
+        let mut world = World::new();
+        world.init_resource::<Events<SceneInstanceReady>>();
+        world.init_resource::<Assets<TiledMapAsset>>();
+
+        let mut registry = ObjectSpawnerRegistry::default();
+        registry.register(ObjectSpawnerKey::Class("Loot".into()), record_context);
+        world.insert_resource(registry);
+
+        let map = world
+            .resource_mut::<Assets<TiledMapAsset>>()
+            .add(TiledMapAsset {
+                map: map(
+                    (4, 4),
+                    vec![TiledLayer::Object(layer(
+                        1,
+                        "Objects",
+                        vec![object("Loot", "Chest")],
+                    ))],
+                    Vec::new(),
+                ),
+                settings: Default::default(),
+                tilemap_textures: Vec::new(),
+                tilemap_atlases: Vec::new(),
+                tilemap_materials: Vec::new(),
+                #[cfg(feature = "sprite3d")]
+                tilemap_standard_materials: Vec::new(),
+                scene: Handle::default(),
+            });
+
+        let spawn_instance = |world: &mut World| {
+            let object = world.spawn((TiledObjectId(1), SpawnedWith::default())).id();
+            let layer = world.spawn(TiledLayerId(1)).add_child(object).id();
+            let container = world.spawn(map.clone()).add_child(layer).id();
+            (container, layer, object)
+        };
+        let (container, layer, object) = spawn_instance(&mut world);
+        let (_, _, other_object) = spawn_instance(&mut world);
+        let spawned_with =
+            |world: &World, object| world.get::<SpawnedWith>(object).unwrap().0.clone();
+
+        // Nothing runs before the scene is instantiated.
+        world.run_system_once(run_object_spawners);
+        assert!(spawned_with(&world, object).is_empty());
+
+        world.send_event(SceneInstanceReady { parent: container });
+        world.run_system_once(run_object_spawners);
+
+        assert_eq!(spawned_with(&world, object), [(map.clone(), layer)]);
+        // Only the objects of the instance that is ready are spawned.
+        assert!(spawned_with(&world, other_object).is_empty());
+    }
+
+    #[test]
+    fn tile_objects_take_the_class_of_their_tile() {
+        let tile_sets = [tile_set([(
+            2,
+            TileAuxInfo {
+                class: "Loot".into(),
+                properties: properties(&[("value", TiledPropertyType::Int(3))]),
+                objects: Vec::new(),
+            },
+        )])];
+        let tile_object = |class: &str, gid: Option<u32>| Object {
+            tile: gid.map(layer_tile),
+            ..object(class, "")
+        };
+
+        assert_eq!(object_class(&tile_object("", Some(3)), &tile_sets), "Loot");
+        assert_eq!(
+            object_class(&tile_object("Trap", Some(3)), &tile_sets),
+            "Trap"
+        );
+        // Tiles without aux info, and objects without tiles, have no class.
+        assert_eq!(object_class(&tile_object("", Some(1)), &tile_sets), "");
+        assert_eq!(object_class(&tile_object("", None), &tile_sets), "");
+    }
+
+    #[test]
+    fn tile_storage_follows_tiles() {
+        let mut world = World::new();
+        let layer = world.spawn(TileStorage::new(UVec2::new(4, 4))).id();
+        let a = world.spawn((TilePos::new(0, 0), TileLayerId(layer))).id();
+        let b = world.spawn((TilePos::new(1, 2), TileLayerId(layer))).id();
+        world.run_system_once(sync_tile_storage);
+
+        let storage = |world: &World, x, y| {
+            world
+                .get::<TileStorage>(layer)
+                .unwrap()
+                .get(TilePos::new(x, y))
+        };
+        assert_eq!(storage(&world, 0, 0), Some(a));
+        assert_eq!(storage(&world, 1, 2), Some(b));
+
+        world.get_mut::<TilePos>(a).unwrap().x = 3;
+        world.despawn(b);
+        world.run_system_once(sync_tile_storage);
+
+        assert_eq!(storage(&world, 0, 0), None);
+        assert_eq!(storage(&world, 3, 0), Some(a));
+        assert_eq!(storage(&world, 1, 2), None);
+    }
+
+    #[test]
+    fn chunk_meshes_wait_for_their_atlas() {
+        let mut world = World::new();
+        world.init_resource::<Assets<TextureAtlasLayout>>();
+        world.init_resource::<Assets<Mesh>>();
+
+        let layout = world
+            .resource::<Assets<TextureAtlasLayout>>()
+            .reserve_handle();
+        let chunk = world
+            .spawn((
+                TileChunk {
+                    origin: TilePos::new(0, 0),
+                    size: UVec2::new(1, 1),
+                    tiles: vec![Some(ChunkTile {
+                        index: 0,
+                        flip_h: false,
+                        flip_v: false,
+                        flip_d: false,
+                        elevation: 0,
+                        side_index: 0,
+                    })],
+                    layout: layout.clone(),
+                    cell_size: Vec2::splat(16.),
+                    tile_size: Vec2::splat(16.),
+                    y_sign: -1.,
+                    opacity: 1.,
+                    step_height: 0.,
+                },
+                Mesh2dHandle::default(),
+            ))
+            .id();
+        let has_mesh = |world: &World| {
+            let Mesh2dHandle(mesh) = world.get::<Mesh2dHandle>(chunk).unwrap();
+            world.resource::<Assets<Mesh>>().contains(mesh)
+        };
+
+        // NOTE:
+        // Unlike `run_system_once`, a registered system keeps its change ticks between runs, so
+        // the chunk isn't seen as changed anymore on the second one.
+        let update = world.register_system(update_tile_chunk_meshes);
+        world.run_system(update).unwrap();
+        assert!(!has_mesh(&world));
+
+        world.resource_mut::<Assets<TextureAtlasLayout>>().insert(
+            &layout,
+            TextureAtlasLayout::from_grid(UVec2::splat(16), 2, 2, None, None),
+        );
+        world.run_system(update).unwrap();
+        assert!(has_mesh(&world));
+    }
+
+    #[test]
+    fn layer_tiles_fall_back_to_chunks() {
+        let chunk_tile = |index| ChunkTile {
+            index,
+            flip_h: false,
+            flip_v: false,
+            flip_d: false,
+            elevation: 0,
+            side_index: index,
+        };
+
+        let mut world = World::new();
+        let layer = world.spawn(TileStorage::new(UVec2::new(4, 4))).id();
+        let chunk = world
+            .spawn(TileChunk {
+                origin: TilePos::new(2, 2),
+                size: UVec2::new(2, 2),
+                tiles: vec![Some(chunk_tile(5)), None, None, Some(chunk_tile(7))],
+                layout: Handle::default(),
+                cell_size: Vec2::splat(16.),
+                tile_size: Vec2::splat(16.),
+                y_sign: -1.,
+                opacity: 1.,
+                step_height: 0.,
+            })
+            .set_parent(layer)
+            .id();
+        let tile = world.spawn((TilePos::new(3, 3), TileLayerId(layer))).id();
+        world.run_system_once(sync_tile_storage);
+
+        let get = |world: &mut World, x, y| {
+            world.run_system_once(move |tiles: LayerTiles| tiles.get(layer, TilePos::new(x, y)))
+        };
+        assert_eq!(
+            get(&mut world, 2, 2),
+            Some(LayerTileRef::Chunk {
+                chunk,
+                tile: chunk_tile(5)
+            })
+        );
+        assert_eq!(get(&mut world, 3, 3), Some(LayerTileRef::Entity(tile)));
+        assert_eq!(get(&mut world, 3, 2), None);
+        assert_eq!(get(&mut world, 0, 0), None);
+    }
+}
@@ -0,0 +1,89 @@
+//! Fixtures shared by the tests of the crate.
+
+use tiled_parse::data_types::{
+    Gid, Image, Layer, LayerHierarchy, LayerTile, Object, ObjectType, Properties, TileAuxInfo,
+    TileSet, TiledLayer, TiledMap, TiledPropertyType,
+};
+
+/// A 16 by 16 rectangle at the origin, without properties.
+pub(crate) fn object(class: &str, name: &str) -> Object {
+    Object {
+        id: 1,
+        name: name.into(),
+        class: class.into(),
+        position: (0., 0.),
+        size: Some((16., 16.)),
+        rotation: 0.,
+        tile: None,
+        visible: true,
+        otype: ObjectType::Rectangle,
+        properties: Default::default(),
+    }
+}
+
+pub(crate) fn properties(properties: &[(&str, TiledPropertyType)]) -> Properties {
+    properties
+        .iter()
+        .map(|(name, p)| (name.to_string(), p.clone()))
+        .collect()
+}
+
+pub(crate) fn layer<T>(id: u32, name: &str, content: T) -> Layer<T> {
+    Layer {
+        id,
+        name: name.into(),
+        class: String::new(),
+        content,
+        visible: true,
+        opacity: 1.,
+        offset: (0., 0.),
+        parallax: (1., 1.),
+        properties: Default::default(),
+    }
+}
+
+pub(crate) fn layer_tile(gid: u32) -> LayerTile {
+    LayerTile {
+        tile: Gid(gid),
+        flip_h: false,
+        flip_v: false,
+        flip_d: false,
+    }
+}
+
+/// A tileset of 16 by 16 tiles starting at gid `1`, with aux info for some of its tiles.
+pub(crate) fn tile_set(tiles: impl IntoIterator<Item = (u32, TileAuxInfo)>) -> TileSet {
+    TileSet {
+        tile_size: (16, 16),
+        first_gid: 1,
+        name: "Tiles".into(),
+        spacing: 0,
+        margin: 0,
+        object_alignment: Default::default(),
+        image: Image {
+            source: "tiles.png".into(),
+            dimensions: (4, 4),
+            format: "png".into(),
+        },
+        tile_stuff: tiles.into_iter().collect(),
+    }
+}
+
+/// A map of 16 by 16 tiles, its layers in a root group.
+pub(crate) fn map(
+    grid_size: (u32, u32),
+    layers: Vec<TiledLayer>,
+    tile_sets: Vec<TileSet>,
+) -> TiledMap {
+    TiledMap {
+        layers: LayerHierarchy::Node(
+            TiledLayer::Group(layer(0, "", ())),
+            layers.into_iter().map(LayerHierarchy::Leaf).collect(),
+        ),
+        grid_size,
+        tile_size: (16, 16),
+        tile_sets,
+        parallax_origin: (0., 0.),
+        skipped_layers: Vec::new(),
+    }
+}
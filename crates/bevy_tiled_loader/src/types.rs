@@ -1,34 +1,308 @@
-use std::marker::PhantomData;
+use std::any::type_name;
+use std::path::PathBuf;
 
 use bevy::asset::{Asset, Handle};
 use bevy::ecs::bundle::Bundle;
 use bevy::ecs::component::Component;
-use bevy::ecs::entity::Entity;
-use bevy::ecs::reflect;
+use bevy::ecs::entity::{Entity, EntityMapper, MapEntities};
+use bevy::ecs::system::{EntityCommands, Resource};
+use bevy::math::{UVec2, Vec2, Vec3};
 use bevy::reflect::{Reflect, TypePath};
+use bevy::render::view::{InheritedVisibility, ViewVisibility, Visibility};
 use bevy::scene::Scene;
-use bevy::sprite::TextureAtlasLayout;
+use bevy::sprite::{ColorMaterial, TextureAtlasLayout};
 use bevy::transform::components::{GlobalTransform, Transform};
 use bevy::utils::hashbrown::HashMap;
 
-use bincode::ErrorKind;
-use serde::{Deserialize, Serialize};
-use tiled_parse::data_types::TiledMap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tiled_parse::data_types::{Object, ObjectLayer, Properties, TiledMap, TiledPropertyType};
 
 #[derive(Component, Reflect)]
 pub struct TiledMapContainer;
 
+/// Tiled id of the layer a spawned layer entity was created from.
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TiledLayerId(pub u32);
+
+/// Opacity of a layer entity, on top of the opacity of the groups it is in.
+/// Starts as the layer's opacity in Tiled, and can be changed at runtime to fade the layer.
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq)]
+pub struct LayerOpacity(pub f32);
+
+/// Parallax scrolling of a layer entity, applied by
+/// [`tiled_parallax_plugin`](crate::plugin::tiled_parallax_plugin) relative to the
+/// [`ParallaxCamera`].
+///
+/// Only spawned on layers whose parallax factor differs from the groups they are in.
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+pub struct LayerParallax {
+    /// Parallax factor of the layer, multiplied by the factors of the groups it is in (as Tiled
+    /// does).
+    pub factor: Vec2,
+    /// Combined factor of the groups the layer is in. Their own offset already moves the layer
+    /// accordingly.
+    pub parent_factor: Vec2,
+    /// The map's parallax origin, in the map container's space.
+    pub origin: Vec2,
+    /// Translation of the layer without any parallax offset.
+    pub base_translation: Vec3,
+}
+
+/// Marks the camera parallax layers are scrolled relative to.
+#[derive(Component, Reflect, Clone, Copy, Debug, Default)]
+pub struct ParallaxCamera;
+
+/// Position of a tile entity on the grid of its layer, in Tiled's `+ y` down coordinates.
+#[derive(Component, Reflect, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TilePos {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TilePos {
+    pub fn new(x: u32, y: u32) -> Self {
+        TilePos { x, y }
+    }
+}
+
+/// The tile layer entity a tile entity is stored in. See [`TileStorage`].
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileLayerId(pub Entity);
+
+impl MapEntities for TileLayerId {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.0 = entity_mapper.map_entity(self.0);
+    }
+}
+
+/// The tile entities of a tile layer entity, by [`TilePos`].
+///
+/// Tiles drawn by [`TileChunk`]s only get an entity when they carry something, plain tiles aren't
+/// stored here. [`LayerTiles`](crate::systems::LayerTiles) finds them too.
+///
+/// Kept in sync with the tiles added, moved or removed at runtime by
+/// [`sync_tile_storage`](crate::systems::sync_tile_storage).
+#[derive(Component, Reflect, Clone, Debug, Default)]
+pub struct TileStorage {
+    size: UVec2,
+    tiles: Vec<Option<Entity>>,
+}
+
+impl TileStorage {
+    pub fn new(size: UVec2) -> Self {
+        TileStorage {
+            size,
+            tiles: vec![None; (size.x * size.y) as usize],
+        }
+    }
+
+    /// Size of the layer's grid, in tiles.
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    pub fn in_bounds(&self, pos: TilePos) -> bool {
+        self.index(pos).is_some()
+    }
+
+    pub fn get(&self, pos: TilePos) -> Option<Entity> {
+        self.index(pos).and_then(|i| self.tiles[i])
+    }
+
+    /// Stores `tile` at `pos`, returning the tile that was there. Positions outside of the grid
+    /// are ignored.
+    pub fn set(&mut self, pos: TilePos, tile: Entity) -> Option<Entity> {
+        self.index(pos).and_then(|i| self.tiles[i].replace(tile))
+    }
+
+    pub fn remove(&mut self, pos: TilePos) -> Option<Entity> {
+        self.index(pos).and_then(|i| self.tiles[i].take())
+    }
+
+    /// The stored tiles, with their position.
+    pub fn iter(&self) -> impl Iterator<Item = (TilePos, Entity)> + '_ {
+        let width = self.size.x as usize;
+        self.tiles.iter().enumerate().filter_map(move |(i, tile)| {
+            tile.map(|t| (TilePos::new((i % width) as u32, (i / width) as u32), t))
+        })
+    }
+
+    fn index(&self, TilePos { x, y }: TilePos) -> Option<usize> {
+        (x < self.size.x && y < self.size.y).then(|| (y * self.size.x + x) as usize)
+    }
+}
+
+impl MapEntities for TileStorage {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.tiles
+            .iter_mut()
+            .flatten()
+            .for_each(|e| *e = entity_mapper.map_entity(*e));
+    }
+}
+
+/// A chunk of a tile layer drawn as a single mesh, in [`TileRenderMode::Chunks`] (or as a
+/// `bevy_ecs_tilemap` tilemap). Only draws the tiles of one tileset.
+///
+/// Its mesh is rebuilt by [`update_tile_chunk_meshes`](crate::systems::update_tile_chunk_meshes)
+/// when it changes.
+#[derive(Component, Reflect, Clone, Debug)]
+pub struct TileChunk {
+    /// Position of the chunk's first tile on the layer's grid.
+    pub origin: TilePos,
+    pub size: UVec2,
+    /// The chunk's tiles, row by row.
+    pub tiles: Vec<Option<ChunkTile>>,
+    /// Atlas of the chunk's tileset.
+    pub layout: Handle<TextureAtlasLayout>,
+    /// Size of the map's grid cells.
+    pub cell_size: Vec2,
+    /// Size of the tileset's tiles.
+    pub tile_size: Vec2,
+    pub y_sign: f32,
+    pub opacity: f32,
+    /// Height of a step of [`ChunkTile::elevation`], in world units. `0` unless the tiles are
+    /// extruded.
+    pub step_height: f32,
+}
+
+impl TileChunk {
+    pub fn get(&self, pos: TilePos) -> Option<ChunkTile> {
+        self.index(pos).and_then(|i| self.tiles[i])
+    }
+
+    /// Sets the tile at `pos`, a position of the layer. Positions outside of the chunk are
+    /// ignored.
+    pub fn set(&mut self, pos: TilePos, tile: Option<ChunkTile>) {
+        if let Some(i) = self.index(pos) {
+            self.tiles[i] = tile;
+        }
+    }
+
+    pub fn contains(&self, pos: TilePos) -> bool {
+        self.index(pos).is_some()
+    }
+
+    fn index(&self, TilePos { x, y }: TilePos) -> Option<usize> {
+        let (x, y) = (x.checked_sub(self.origin.x)?, y.checked_sub(self.origin.y)?);
+        (x < self.size.x && y < self.size.y).then(|| (y * self.size.x + x) as usize)
+    }
+}
+
+/// A tile of a [`TileChunk`].
+#[derive(Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkTile {
+    /// Index of the tile in its tileset.
+    pub index: u32,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub flip_d: bool,
+    /// Number of steps the tile is raised by, from its elevation property.
+    pub elevation: u32,
+    /// Index of the tile drawn on the sides of the raised tile.
+    pub side_index: u32,
+}
+
+/// Tiled id of the object a spawned object entity was created from.
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TiledObjectId(pub u32);
+
+/// Mirrors [`ObjectType`](tiled_parse::data_types::ObjectType), in the object entity's local
+/// space (`y` already follows [`TiledLoaderSettings::y_axis`]).
+///
+/// The origin is the object's position in Tiled : the top-left corner for rectangles and
+/// ellipses, and the first point's reference for polygons and polylines.
+#[derive(Component, Reflect, Clone, Debug)]
+pub enum TiledObjectShape {
+    Rectangle { size: Vec2 },
+    Ellipse { size: Vec2 },
+    Point,
+    Polygon { points: Vec<Vec2> },
+    Polyline { points: Vec<Vec2> },
+}
+
+/// The custom properties of a spawned object.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+pub struct TiledProperties(pub HashMap<String, TiledProperty>);
+
+/// Mirrors [`TiledPropertyType`], which can't be reflected.
+#[derive(Reflect, Clone, Debug, PartialEq)]
+pub enum TiledProperty {
+    String(String),
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+    File(PathBuf),
+    Object(u32),
+}
+
+/// Entities of the objects of a map, by Tiled id. On the map container.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+pub struct TiledObjectEntities(pub HashMap<u32, Entity>);
+
+/// The object properties of a spawned object or layer, as the entities of the objects they point
+/// at.
+#[derive(Component, Reflect, Clone, Debug, Default)]
+pub struct TiledObjectRefs(pub HashMap<String, Entity>);
+
+// NOTE:
+// Entities are remapped when the map scene is instantiated.
+impl MapEntities for TiledObjectEntities {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.0
+            .values_mut()
+            .for_each(|e| *e = entity_mapper.map_entity(*e));
+    }
+}
+
+impl MapEntities for TiledObjectRefs {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.0
+            .values_mut()
+            .for_each(|e| *e = entity_mapper.map_entity(*e));
+    }
+}
+
+impl From<&TiledPropertyType> for TiledProperty {
+    fn from(p: &TiledPropertyType) -> Self {
+        match p {
+            TiledPropertyType::String(v) => TiledProperty::String(v.clone()),
+            TiledPropertyType::Int(v) => TiledProperty::Int(*v),
+            TiledPropertyType::Float(v) => TiledProperty::Float(*v),
+            TiledPropertyType::Bool(v) => TiledProperty::Bool(*v),
+            TiledPropertyType::File(v) => TiledProperty::File(v.clone()),
+            TiledPropertyType::Object(v) => TiledProperty::Object(*v),
+        }
+    }
+}
+
+impl From<&Properties> for TiledProperties {
+    fn from(p: &Properties) -> Self {
+        TiledProperties(p.iter().map(|(k, v)| (k.clone(), v.into())).collect())
+    }
+}
+
 #[derive(TypePath, Asset)]
 pub struct TiledMapAsset {
     pub map: TiledMap,
+    /// The settings the map was loaded with.
+    pub settings: TiledLoaderSettings,
 
     // TODO:
     // pub colliders: todo!(),
     pub tilemap_textures: Vec<Handle<bevy::prelude::Image>>,
     pub tilemap_atlases: Vec<Handle<TextureAtlasLayout>>,
+    /// Materials of the tilesets' images, for [`TileRenderMode::Chunks`].
+    pub tilemap_materials: Vec<Handle<ColorMaterial>>,
+    /// Materials of the tilesets' images, for [`MapProjection::Plane3d`]. Empty for other
+    /// projections.
+    #[cfg(feature = "sprite3d")]
+    pub tilemap_standard_materials: Vec<Handle<bevy::pbr::StandardMaterial>>,
     pub scene: Handle<Scene>,
 }
 
+/// Spawns the scene of a map as children of the entity once the map is loaded, and then runs the
+/// [`ObjectSpawner`]s of its objects.
 // TODO:
 // I'm not sure that I want to have the crate commit to this instance implementation...
 // For example, GPU rendering would be more efficient (like `bevy_ecs_tilemap`)
@@ -37,33 +311,472 @@ pub struct TiledMapBundle {
     pub tiled_map: Handle<TiledMapAsset>,
     pub transform: Transform,
     pub global_transform: GlobalTransform,
+    pub visibility: Visibility,
+    pub inherited_visibility: InheritedVisibility,
+    pub view_visibility: ViewVisibility,
+}
+
+/// Selects the objects an [`ObjectSpawner`] runs for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectSpawnerKey {
+    /// Objects of this class. Tile objects without a class have their tile's.
+    Class(String),
+    /// Objects with this name.
+    Name(String),
+}
+
+/// What an [`ObjectSpawner`] is given besides the object.
+pub struct ObjectSpawnContext<'a> {
+    /// The map the object is part of.
+    pub map: &'a Handle<TiledMapAsset>,
+    /// The layer the object is in.
+    pub layer: &'a ObjectLayer,
+    /// The entity of the layer, which the object's entity is a child of.
+    pub layer_entity: Entity,
+}
+
+/// Setup code run on the entity of an object, once the scene of its map is instantiated.
+pub type ObjectSpawner = fn(&Object, &ObjectSpawnContext, &mut EntityCommands);
+
+/// The [`ObjectSpawner`]s of objects, by class and by name.
+///
+/// Filled through [`register_object_spawner`](crate::plugin::ObjectSpawnerAppExt).
+#[derive(Resource, Default)]
+pub struct ObjectSpawnerRegistry {
+    spawners: HashMap<ObjectSpawnerKey, Vec<ObjectSpawner>>,
+}
+
+impl ObjectSpawnerRegistry {
+    pub fn register(&mut self, key: ObjectSpawnerKey, spawner: ObjectSpawner) {
+        self.spawners.entry(key).or_default().push(spawner);
+    }
+
+    /// The spawners of an object with this class and name, in the order they were registered,
+    /// class ones first.
+    pub fn get(&self, class: &str, name: &str) -> impl Iterator<Item = &ObjectSpawner> {
+        let spawners = move |key: Option<ObjectSpawnerKey>| {
+            key.and_then(|k| self.spawners.get(&k))
+                .into_iter()
+                .flatten()
+        };
+
+        spawners((!class.is_empty()).then(|| ObjectSpawnerKey::Class(class.into()))).chain(
+            spawners((!name.is_empty()).then(|| ObjectSpawnerKey::Name(name.into()))),
+        )
+    }
 }
 
+/// Components that aren't `Reflect`, stored in the map scene with `bincode`. They replace this
+/// component when the scene is spawned, if their type was registered in the
+/// [`SerializedComponentRegistry`].
 // NOTE:
 // While this could be made further general to any Serailizer, because of how `serde` Serailizers
 // are suggested to be written (the result byte stream stored within the instance, without a clear
 // way of extracting it), I cannot easily generalize it...
-#[derive(Component, Reflect)]
-// pub struct Serialized<T: Serialize + for<'de> Deserialize<'de>> {
+#[derive(Component, Reflect, Default)]
 pub struct Serialized {
+    pub components: Vec<SerializedComponent>,
+}
+
+#[derive(Reflect, Clone, Debug)]
+pub struct SerializedComponent {
+    /// [`type_name`] of the component.
+    pub type_name: String,
     pub data: Vec<u8>,
-    pub thingy: SceneSerializedComponents,
-}
-
-#[derive(Reflect)]
-pub enum SceneSerializedComponents {
-    RCollider,
-}
-
-// impl<T> Serialized<T> {
-//     pub fn new(data: T) -> Result<Self, Box<ErrorKind>> {
-//         bincode::serialize(&data).map(|data| Serialized {
-//             data,
-//             _marker: PhantomData,
-//         })
-//     }
-//
-//     pub fn deserialize(&self) -> Result<T, Box<ErrorKind>> {
-//         bincode::deserialize(&self.data)
-//     }
-// }
+}
+
+impl Serialized {
+    pub fn new<T: Component + Serialize>(component: &T) -> bincode::Result<Self> {
+        let mut serialized = Self::default();
+        serialized.push(component)?;
+        Ok(serialized)
+    }
+
+    pub fn push<T: Component + Serialize>(&mut self, component: &T) -> bincode::Result<()> {
+        self.components.push(SerializedComponent {
+            type_name: type_name::<T>().into(),
+            data: bincode::serialize(component)?,
+        });
+        Ok(())
+    }
+}
+
+type InsertSerialized = fn(&[u8], &mut EntityCommands) -> bincode::Result<()>;
+
+/// The component types that can be restored from [`Serialized`], by type name.
+///
+/// Filled through [`register_serialized_component`](crate::plugin::SerializedComponentAppExt).
+#[derive(Resource, Default)]
+pub struct SerializedComponentRegistry {
+    inserters: HashMap<String, InsertSerialized>,
+}
+
+impl SerializedComponentRegistry {
+    pub fn register<T: Component + DeserializeOwned>(&mut self) {
+        self.inserters
+            .insert(type_name::<T>().into(), |data, entity_commands| {
+                entity_commands.insert(bincode::deserialize::<T>(data)?);
+                Ok(())
+            });
+    }
+
+    /// Deserializes `component` and inserts it with `entity_commands`. Returns `None` if its type
+    /// wasn't registered.
+    pub fn insert(
+        &self,
+        SerializedComponent { type_name, data }: &SerializedComponent,
+        entity_commands: &mut EntityCommands,
+    ) -> Option<bincode::Result<()>> {
+        self.inserters
+            .get(type_name)
+            .map(|insert| insert(data, entity_commands))
+    }
+}
+
+/// Per-map options for [`TiledLoader`](crate::load::TiledLoader), picked through
+/// `AssetServer::load_with_settings`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TiledLoaderSettings {
+    /// Where the map's `(0, 0)` ends up relative to the spawned map container.
+    pub origin: MapOrigin,
+    /// Direction of `+ y` in the spawned scene. Tiled uses `+ y` down, Bevy uses `+ y` up.
+    pub y_axis: YAxis,
+    /// Distance along `z` between two consecutive layers.
+    pub layer_z_spacing: f32,
+    /// Number of Tiled pixels per world unit. Applied as a scale on the map container.
+    pub pixels_per_unit: f32,
+    pub spawn_layers: SpawnLayers,
+    pub generate_colliders: bool,
+    /// Which collision shapes of tiles become colliders.
+    pub collider_filter: ColliderFilter,
+    /// How the collision shapes of tiles in tile layers are spawned.
+    pub tile_colliders: TileColliders,
+    /// Number of vertices of the polygon approximating non-circular ellipse colliders.
+    pub ellipse_segments: u32,
+    /// How the tiles of tile layers are drawn.
+    pub render_mode: TileRenderMode,
+    /// Plane the map is laid out on.
+    pub projection: MapProjection,
+}
+
+impl Default for TiledLoaderSettings {
+    fn default() -> Self {
+        Self {
+            origin: MapOrigin::TopLeft,
+            y_axis: YAxis::Up,
+            layer_z_spacing: 1.,
+            pixels_per_unit: 1.,
+            spawn_layers: SpawnLayers::default(),
+            generate_colliders: true,
+            collider_filter: ColliderFilter::default(),
+            tile_colliders: TileColliders::PerTile,
+            ellipse_segments: 32,
+            render_mode: TileRenderMode::Sprites,
+            projection: MapProjection::Flat,
+        }
+    }
+}
+
+impl TiledLoaderSettings {
+    /// Factor to multiply Tiled's `y` coordinates with to get scene coordinates.
+    pub fn y_sign(&self) -> f32 {
+        match self.y_axis {
+            YAxis::Up => -1.,
+            YAxis::Down => 1.,
+        }
+    }
+
+    /// Size of the chunks that draw the tiles of tile layers, unless they are sprites.
+    pub fn chunk_size(&self) -> Option<u32> {
+        match (&self.projection, self.render_mode) {
+            // NOTE:
+            // Sprites aren't drawn by 3d cameras, each tile gets its own mesh instead.
+            #[cfg(feature = "sprite3d")]
+            (MapProjection::Plane3d(_), TileRenderMode::Sprites) => Some(1),
+            (_, render_mode) => render_mode.chunk_size(),
+        }
+    }
+
+    /// Options of the 3d layout, if the map is laid out on the `XZ` plane.
+    #[cfg(feature = "sprite3d")]
+    pub fn plane3d(&self) -> Option<&Plane3dSettings> {
+        match &self.projection {
+            MapProjection::Plane3d(plane3d) => Some(plane3d),
+            MapProjection::Flat => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MapOrigin {
+    /// The top-left corner of the map sits at the container's origin (Tiled's own convention).
+    #[default]
+    TopLeft,
+    /// The center of the map sits at the container's origin.
+    Center,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum YAxis {
+    /// Bevy's convention. Tiled's `y` coordinates are negated.
+    #[default]
+    Up,
+    /// Tiled's convention. Meant for cameras that are themselves flipped, so sprites are flipped
+    /// vertically to still read the right way up.
+    Down,
+}
+
+/// Plane the map is laid out on.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum MapProjection {
+    /// The `XY` plane, for 2d cameras.
+    #[default]
+    Flat,
+    /// The `XZ` plane, for 3d cameras. The map container is rotated so that its `+ z` points up,
+    /// which layers are stacked along.
+    ///
+    /// Tiles are drawn with meshes of `StandardMaterial`s, a mesh per tile with
+    /// [`TileRenderMode::Sprites`], and tile objects with a [`TileChunk`] of their own.
+    #[cfg(feature = "sprite3d")]
+    Plane3d(Plane3dSettings),
+}
+
+/// Options of [`MapProjection::Plane3d`].
+#[cfg(feature = "sprite3d")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Plane3dSettings {
+    /// Name of the `float` or `int` layer property giving the height of a layer above its parent,
+    /// in world units. Layers without it are stacked with
+    /// [`TiledLoaderSettings::layer_z_spacing`].
+    pub height_property: String,
+    /// Height of the colliders extruded from collision shapes, in world units.
+    pub collider_height: f32,
+    /// Raises tiles into blocks, from their properties.
+    pub extrusion: Option<Extrusion>,
+}
+
+#[cfg(feature = "sprite3d")]
+impl Default for Plane3dSettings {
+    fn default() -> Self {
+        Self {
+            height_property: "height".into(),
+            collider_height: 1.,
+            extrusion: None,
+        }
+    }
+}
+
+/// Options of the extrusion of tiles into blocks, drawn by their chunk's mesh.
+///
+/// Chunks get a trimesh collider matching their mesh with
+/// [`TiledLoaderSettings::generate_colliders`] and the `rapier3d_colliders` feature. Sides are
+/// only left out between tiles of the same chunk, so bigger chunks have fewer faces. With
+/// [`TileRenderMode::Sprites`] every tile is its own chunk: it gets its own mesh and trimesh, and
+/// walls down to the ground on all of its sides. Use [`TileRenderMode::Chunks`] for extruded maps.
+#[cfg(feature = "sprite3d")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Extrusion {
+    /// Name of the `int` tile property giving the number of steps a tile is raised by.
+    pub elevation_property: String,
+    /// Name of the `int` tile property giving the id (in the same tileset) of the tile drawn on
+    /// the sides of a raised tile. Tiles without it use their own image.
+    pub side_property: String,
+    /// Height of a step, in world units.
+    pub step_height: f32,
+}
+
+#[cfg(feature = "sprite3d")]
+impl Default for Extrusion {
+    fn default() -> Self {
+        Self {
+            elevation_property: "elevation".into(),
+            side_property: "side_tile".into(),
+            step_height: 1.,
+        }
+    }
+}
+
+/// How the tiles of tile layers are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TileRenderMode {
+    /// A sprite per tile.
+    ///
+    /// With [`MapProjection::Plane3d`], sprites aren't drawn, and each tile gets a chunk of its
+    /// own instead, with one mesh per tile.
+    #[default]
+    Sprites,
+    /// A mesh per square of `size` tiles and tileset, made of [`TileChunk`]s. Tile entities are
+    /// only spawned for tiles with a class, properties or collision shapes, so other tiles are
+    /// missing from the [`TileStorage`] and are looked up with
+    /// [`LayerTiles`](crate::systems::LayerTiles).
+    Chunks { size: u32 },
+    /// A `bevy_ecs_tilemap` tilemap per layer and tileset, built from a [`TileChunk`] once the map
+    /// is spawned. Tile entities are spawned like with [`TileRenderMode::Chunks`].
+    ///
+    /// `TilemapPlugin` must be added to the app. Tileset margins aren't supported. With
+    /// [`MapProjection::Plane3d`], chunks of a whole layer are drawn with meshes instead.
+    #[cfg(feature = "ecs_tilemap")]
+    EcsTilemap,
+}
+
+impl TileRenderMode {
+    /// Size of the chunks that draw the tiles, unless they are sprites.
+    pub fn chunk_size(&self) -> Option<u32> {
+        match self {
+            TileRenderMode::Sprites => None,
+            TileRenderMode::Chunks { size } => Some((*size).max(1)),
+            #[cfg(feature = "ecs_tilemap")]
+            TileRenderMode::EcsTilemap => Some(u32::MAX),
+        }
+    }
+}
+
+/// Which kinds of layers get spawned into the map scene.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SpawnLayers {
+    pub tiles: bool,
+    pub objects: bool,
+}
+
+/// Selects the collision shapes of tiles that become colliders.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ColliderFilter {
+    /// Shapes with this `bool` property set to `true`.
+    Property(String),
+    /// Shapes of this class.
+    Class(String),
+    /// All shapes of tiles placed on layers with this name.
+    LayerName(String),
+    /// All shapes.
+    All,
+    /// Shapes selected by any of the filters.
+    Any(Vec<ColliderFilter>),
+}
+
+impl Default for ColliderFilter {
+    fn default() -> Self {
+        ColliderFilter::Property("collider".into())
+    }
+}
+
+impl ColliderFilter {
+    /// Whether `shape`, a collision shape of a tile placed on the layer named `layer`, is
+    /// selected.
+    pub fn matches(&self, shape: &Object, layer: &str) -> bool {
+        match self {
+            ColliderFilter::Property(name) => matches!(
+                shape.properties.get(name),
+                Some(TiledPropertyType::Bool(true))
+            ),
+            ColliderFilter::Class(class) => shape.class == *class,
+            ColliderFilter::LayerName(name) => layer == name,
+            ColliderFilter::All => true,
+            ColliderFilter::Any(filters) => filters.iter().any(|f| f.matches(shape, layer)),
+        }
+    }
+}
+
+/// How the collision shapes of tiles in tile layers are spawned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TileColliders {
+    /// Each tile gets its own collider entities.
+    #[default]
+    PerTile,
+    /// Unrotated rectangles of all the tiles of a layer are merged into as few rectangles as
+    /// possible, in a single collider on the layer entity. Other shapes stay on their tile.
+    Merged,
+    /// Like [`TileColliders::Merged`], but only the outline of the merged area collides, which
+    /// avoids characters catching on the seams between rectangles.
+    Outline,
+}
+
+impl Default for SpawnLayers {
+    fn default() -> Self {
+        Self {
+            tiles: true,
+            objects: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::{CommandQueue, Commands};
+    use bevy::ecs::world::{EntityWorldMut, World};
+
+    use crate::test_util::{layer, object};
+
+    /// Names of the spawners run on an entity, in order.
+    #[derive(Component, Default)]
+    struct Spawned(Vec<&'static str>);
+
+    fn spawned_by(e: &mut EntityCommands, spawner: &'static str) {
+        e.add(move |mut e: EntityWorldMut| e.get_mut::<Spawned>().unwrap().0.push(spawner));
+    }
+
+    fn class_a(_: &Object, _: &ObjectSpawnContext, e: &mut EntityCommands) {
+        spawned_by(e, "class a")
+    }
+
+    fn class_b(_: &Object, _: &ObjectSpawnContext, e: &mut EntityCommands) {
+        spawned_by(e, "class b")
+    }
+
+    fn name(_: &Object, _: &ObjectSpawnContext, e: &mut EntityCommands) {
+        spawned_by(e, "name")
+    }
+
+    /// Runs the spawners the registry gives for an object with this class and name.
+    fn run_spawners(
+        registry: &ObjectSpawnerRegistry,
+        class: &str,
+        name: &str,
+    ) -> Vec<&'static str> {
+        let mut world = World::new();
+        let e = world.spawn(Spawned::default()).id();
+        let map = Handle::default();
+        let layer: ObjectLayer = layer(1, "Objects", vec![object(class, name)]);
+        let ctx = ObjectSpawnContext {
+            map: &map,
+            layer: &layer,
+            layer_entity: e,
+        };
+
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        registry
+            .get(class, name)
+            .for_each(|spawner| spawner(&layer.content[0], &ctx, &mut commands.entity(e)));
+        queue.apply(&mut world);
+
+        world.entity_mut(e).take::<Spawned>().unwrap().0
+    }
+
+    #[test]
+    fn class_spawners_run_before_name_ones() {
+        let mut registry = ObjectSpawnerRegistry::default();
+        registry.register(ObjectSpawnerKey::Name("Chest".into()), name);
+        registry.register(ObjectSpawnerKey::Class("Loot".into()), class_a);
+        registry.register(ObjectSpawnerKey::Class("Loot".into()), class_b);
+
+        assert_eq!(
+            run_spawners(&registry, "Loot", "Chest"),
+            ["class a", "class b", "name"]
+        );
+        assert_eq!(
+            run_spawners(&registry, "Loot", "Barrel"),
+            ["class a", "class b"]
+        );
+        assert_eq!(run_spawners(&registry, "Trap", "Chest"), ["name"]);
+    }
+
+    #[test]
+    fn empty_class_and_name_match_nothing() {
+        let mut registry = ObjectSpawnerRegistry::default();
+        registry.register(ObjectSpawnerKey::Class(String::new()), class_a);
+        registry.register(ObjectSpawnerKey::Name(String::new()), name);
+
+        assert!(run_spawners(&registry, "", "").is_empty());
+    }
+}
@@ -3,6 +3,8 @@ use std::{collections::HashMap, path::PathBuf, str::FromStr};
 use ndarray::Array2;
 use tree::Tree;
 
+use crate::error::ElementRef;
+
 pub type ID = u32;
 
 pub type PairU32 = (u32, u32);
@@ -90,15 +92,41 @@ pub struct LayerTile {
     pub flip_d: bool,
 }
 
+impl LayerTile {
+    /// Decodes a GID as stored in the map, with its flip flags in the upper bits.
+    /// Returns `None` for the empty tile.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        let flags = bits & ALL_FLIP_FLAGS;
+
+        let gid = Gid(bits & !ALL_FLIP_FLAGS);
+        let flip_d = flags & FLIPPED_DIAGONALLY_FLAG == FLIPPED_DIAGONALLY_FLAG; // Swap x and y axis (anti-diagonally) [flips over y = -x line]
+        let flip_h = flags & FLIPPED_HORIZONTALLY_FLAG == FLIPPED_HORIZONTALLY_FLAG; // Flip tile over y axis
+        let flip_v = flags & FLIPPED_VERTICALLY_FLAG == FLIPPED_VERTICALLY_FLAG; // Flip tile over x axis
+
+        if gid == Gid::EMPTY {
+            None
+        } else {
+            Some(LayerTile {
+                tile: gid,
+                flip_h,
+                flip_v,
+                flip_d,
+            })
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Object {
     pub id: ID,
-    // pub tile_type: String,
+    pub name: String,
+    // Named `type` before Tiled 1.9.
+    pub class: String,
     pub position: PairF32,
     pub size: Option<PairF32>,
     pub rotation: f32,
-    // If the object is attached to a Tile, this field will exist.
-    pub tile_global_id: Option<Gid>,
+    // If the object is attached to a Tile (a "tile object"), this field will exist.
+    pub tile: Option<LayerTile>,
     pub visible: bool,
     pub otype: ObjectType,
     pub properties: Properties,
@@ -108,7 +136,7 @@ pub struct Object {
 pub struct Layer<T> {
     pub id: ID,
     pub name: String,
-    // pub class: String,
+    pub class: String,
     // Cannot be modified in Tiled
     // _pos: PairU32
     // Always same as Map size
@@ -116,7 +144,10 @@ pub struct Layer<T> {
     pub content: T,
     pub visible: bool,
     pub opacity: f32,
+    // In pixels, relative to the parent group.
+    pub offset: PairF32,
     pub parallax: (f32, f32),
+    pub properties: Properties,
 }
 
 // TODO:
@@ -150,6 +181,8 @@ pub struct Image {
 
 #[derive(Debug)]
 pub struct TileAuxInfo {
+    // Named `type` before Tiled 1.9.
+    pub class: String,
     // Can contain at most one: <properties>, <image> (since 0.9), <objectgroup>, <animation>
     // pub color: Color,
     // pub animation: ObjectGroup,
@@ -160,6 +193,43 @@ pub struct TileAuxInfo {
     pub objects: Vec<Object>,
 }
 
+/// Which point of a tile object its position refers to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ObjectAlignment {
+    // For orthogonal maps, this is the same as `BottomLeft`.
+    #[default]
+    Unspecified,
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl FromStr for ObjectAlignment {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "unspecified" => ObjectAlignment::Unspecified,
+            "topleft" => ObjectAlignment::TopLeft,
+            "top" => ObjectAlignment::Top,
+            "topright" => ObjectAlignment::TopRight,
+            "left" => ObjectAlignment::Left,
+            "center" => ObjectAlignment::Center,
+            "right" => ObjectAlignment::Right,
+            "bottomleft" => ObjectAlignment::BottomLeft,
+            "bottom" => ObjectAlignment::Bottom,
+            "bottomright" => ObjectAlignment::BottomRight,
+            _ => return Err(()),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct TileSet {
     pub tile_size: PairU32,
@@ -167,6 +237,7 @@ pub struct TileSet {
     pub name: String,
     pub spacing: u8,
     pub margin: u8,
+    pub object_alignment: ObjectAlignment,
     // NOTE:
     // Removed for now because it's better to rely on `first_gid`
     // tile_count: u32,
@@ -186,4 +257,8 @@ pub struct TiledMap {
     pub grid_size: PairU32,
     pub tile_size: PairU32,
     pub tile_sets: Vec<TileSet>,
+    // In pixels. The point parallax factors are relative to.
+    pub parallax_origin: PairF32,
+    // Layers left out of `layers`, because they aren't handled yet.
+    pub skipped_layers: Vec<ElementRef>,
 }
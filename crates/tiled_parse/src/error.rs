@@ -0,0 +1,79 @@
+use std::fmt::Display;
+
+use nom_xml::types::Tag;
+
+/// Describes which part of a TMX document could not be understood.
+#[derive(Debug, Clone)]
+pub enum TiledParseError {
+    /// The document is not well formed XML.
+    Xml(String),
+    /// A required attribute is missing, or its value could not be parsed.
+    Attribute {
+        element: ElementRef,
+        attribute: String,
+    },
+    /// A required child element is missing.
+    MissingElement { parent: ElementRef, element: String },
+    /// The `<data>` of a tile layer could not be read.
+    LayerData { layer: ElementRef, details: String },
+    /// The element is valid Tiled, but not handled by this crate (yet).
+    Unsupported {
+        element: ElementRef,
+        details: String,
+    },
+}
+
+/// Identifies an element of the document in errors : its tag, and its `id` or `name` when it
+/// has one.
+#[derive(Debug, Clone)]
+pub struct ElementRef {
+    pub tag: String,
+    pub id: Option<String>,
+    pub name: Option<String>,
+}
+
+impl From<&Tag> for ElementRef {
+    fn from(t: &Tag) -> Self {
+        ElementRef {
+            tag: t.value.clone(),
+            id: t.attributes.get("id").cloned(),
+            name: t.attributes.get("name").cloned(),
+        }
+    }
+}
+
+impl Display for ElementRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{}", self.tag)?;
+        if let Some(id) = &self.id {
+            write!(f, " id=\"{id}\"")?;
+        }
+        if let Some(name) = &self.name {
+            write!(f, " name=\"{name}\"")?;
+        }
+        write!(f, ">")
+    }
+}
+
+impl Display for TiledParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TiledParseError::Xml(details) => write!(f, "invalid XML: {details}"),
+            TiledParseError::Attribute { element, attribute } => write!(
+                f,
+                "{element} is missing attribute `{attribute}`, or its value is invalid"
+            ),
+            TiledParseError::MissingElement { parent, element } => {
+                write!(f, "{parent} is missing a <{element}> element")
+            }
+            TiledParseError::LayerData { layer, details } => {
+                write!(f, "could not read the data of {layer}: {details}")
+            }
+            TiledParseError::Unsupported { element, details } => {
+                write!(f, "{element} is not supported: {details}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TiledParseError {}
@@ -1,4 +1,5 @@
 pub mod data_types;
+pub mod error;
 pub mod parse;
 pub mod relations;
 pub(crate) mod util;
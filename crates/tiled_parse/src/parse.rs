@@ -8,79 +8,91 @@ use nom_xml::{
 
 use crate::{
     data_types::*,
+    error::{ElementRef, TiledParseError},
     util::{parse_spaced_f32_pairs, parse_tiles_csv},
 };
 
-pub fn parse<'a>(i: &'a str) -> Result<TiledMap, ()> {
-    let tmx_root = Xml::from_input_str(i).unwrap();
+pub fn parse<'a>(i: &'a str) -> Result<TiledMap, TiledParseError> {
+    let tmx_root = Xml::from_input_str(i).map_err(|e| TiledParseError::Xml(format!("{e:?}")))?;
     let Xml::Element(map_tag, Some(elements)) = &tmx_root else {
-        panic!("oh shit")
+        return Err(TiledParseError::Xml(
+            "The document root should be a non-empty <map> element".into(),
+        ));
     };
 
-    let tile_sets = get_tile_sets(&elements);
+    let tile_sets = get_tile_sets(&elements)?;
 
     Ok(TiledMap {
-        grid_size: (
-            get_parse::<u32>(&map_tag.attributes, "width").unwrap(),
-            get_parse::<u32>(&map_tag.attributes, "height").unwrap(),
-        ),
+        grid_size: (require(map_tag, "width")?, require(map_tag, "height")?),
         tile_size: (
-            get_parse::<u32>(&map_tag.attributes, "tilewidth").unwrap(),
-            get_parse::<u32>(&map_tag.attributes, "tileheight").unwrap(),
+            require(map_tag, "tilewidth")?,
+            require(map_tag, "tileheight")?,
+        ),
+        parallax_origin: (
+            get_parse(&map_tag.attributes, "parallaxoriginx").unwrap_or(0.),
+            get_parse(&map_tag.attributes, "parallaxoriginy").unwrap_or(0.),
         ),
-        layers: parse_layers(&tile_sets, &tmx_root).unwrap(),
+        layers: parse_layers(&tile_sets, &tmx_root)?.ok_or_else(|| {
+            TiledParseError::MissingElement {
+                parent: map_tag.into(),
+                element: "layer".into(),
+            }
+        })?,
         tile_sets,
+        skipped_layers: skipped_layers(&tmx_root),
     })
 }
 
-fn get_tile_sets(elements: &Vec<Xml>) -> Vec<TileSet> {
+fn get_tile_sets(elements: &Vec<Xml>) -> Result<Vec<TileSet>, TiledParseError> {
     elements
         .iter()
-        .filter_map(|x| tile_set_element(&x))
+        .filter_map(|x| tile_set_element(&x).transpose())
         .collect()
 }
 
-fn tile_set_element(x: &Xml) -> Option<TileSet> {
+fn tile_set_element(x: &Xml) -> Result<Option<TileSet>, TiledParseError> {
     let Xml::Element(t, Some(e)) = x else {
-        return None;
+        return Ok(None);
     };
 
     if t.value != "tileset" {
-        return None;
+        return Ok(None);
     }
 
-    let first_gid = get_parse::<u32>(&t.attributes, "firstgid").unwrap();
+    let first_gid = require::<u32>(t, "firstgid")?;
 
     let tile_size = (
-        get_parse::<u32>(&t.attributes, "tilewidth").unwrap(),
-        get_parse::<u32>(&t.attributes, "tileheight").unwrap(),
+        require::<u32>(t, "tilewidth")?,
+        require::<u32>(t, "tileheight")?,
     );
 
-    Some(TileSet {
+    Ok(Some(TileSet {
         tile_size,
         first_gid,
-        name: t.attributes.get("name").unwrap().clone(),
+        name: require(t, "name")?,
         margin: get_parse::<u8>(&t.attributes, "margin").unwrap_or(0),
         spacing: get_parse::<u8>(&t.attributes, "spacing").unwrap_or(0),
-        image: e
-            .iter()
-            .find(|x| x.tag_has_name("image"))
-            .map(|xml_element| match xml_element {
-                Xml::Element(img_tag, _) => Image {
-                    source: img_tag.attributes.get("source").unwrap().into(),
-                    dimensions: (
-                        get_parse::<u32>(&img_tag.attributes, "width").unwrap() / tile_size.0,
-                        get_parse::<u32>(&img_tag.attributes, "height").unwrap() / tile_size.1,
-                    ),
-                    format: img_tag
-                        .attributes
-                        .get("format")
-                        .unwrap_or(&"png".into())
-                        .clone(),
-                },
-                _ => unreachable!(), // This will panic if Xml::Element is not matched
-            })
-            .expect("Tile set should contain an image."),
+        object_alignment: get_parse(&t.attributes, "objectalignment").unwrap_or_default(),
+        image: match e.iter().find(|x| x.tag_has_name("image")) {
+            Some(Xml::Element(img_tag, _)) => Image {
+                source: require::<String>(img_tag, "source")?.into(),
+                dimensions: (
+                    require::<u32>(img_tag, "width")? / tile_size.0,
+                    require::<u32>(img_tag, "height")? / tile_size.1,
+                ),
+                format: img_tag
+                    .attributes
+                    .get("format")
+                    .unwrap_or(&"png".into())
+                    .clone(),
+            },
+            _ => {
+                return Err(TiledParseError::MissingElement {
+                    parent: t.into(),
+                    element: "image".into(),
+                })
+            }
+        },
         tile_stuff: e
             .iter()
             .filter_map(|x| {
@@ -90,67 +102,91 @@ fn tile_set_element(x: &Xml) -> Option<TileSet> {
                 let Xml::Element(tile_tag, Some(tile_elems)) = x else {
                     return None;
                 };
-                let id = get_parse::<u32>(&tile_tag.attributes, "id")?;
-                let properties = parse_tmx_properties(x).unwrap_or_default();
-                let objects = tile_elems
-                    .iter()
-                    .find(|t_e| t_e.tag_has_name("objectgroup"))
-                    .and_then(|ogroup_xml| match ogroup_xml {
-                        // NOTE:
-                        // It's necessary to wrap in a new Option like this because then `objects`
-                        // is a reference.
-                        Xml::Element(_, Some(objects)) => Some(objects),
-                        _ => None,
-                    })
-                    .unwrap_or(&vec![])
-                    .iter()
-                    .filter_map(object_parse)
-                    .collect();
-
-                Some((
-                    id,
-                    TileAuxInfo {
-                        properties,
-                        objects,
-                    },
-                ))
+
+                Some(parse_tile_aux_info(tile_tag, tile_elems, x))
             })
-            .collect(),
-    })
+            .collect::<Result<_, _>>()?,
+    }))
 }
 
-fn parse_tmx_properties(x: &Xml) -> Option<Properties> {
+fn parse_tile_aux_info(
+    tile_tag: &Tag,
+    tile_elems: &Vec<Xml>,
+    x: &Xml,
+) -> Result<(u32, TileAuxInfo), TiledParseError> {
+    let id = require::<u32>(tile_tag, "id")?;
+    let properties = parse_tmx_properties(x)?.unwrap_or_default();
+    let objects = tile_elems
+        .iter()
+        .find(|t_e| t_e.tag_has_name("objectgroup"))
+        .and_then(|ogroup_xml| match ogroup_xml {
+            // NOTE:
+            // It's necessary to wrap in a new Option like this because then `objects`
+            // is a reference.
+            Xml::Element(_, Some(objects)) => Some(objects),
+            _ => None,
+        })
+        .unwrap_or(&vec![])
+        .iter()
+        .filter_map(|o| object_parse(o).transpose())
+        .collect::<Result<_, _>>()?;
+
+    Ok((
+        id,
+        TileAuxInfo {
+            class: class(tile_tag),
+            properties,
+            objects,
+        },
+    ))
+}
+
+fn parse_tmx_properties(x: &Xml) -> Result<Option<Properties>, TiledParseError> {
     let Xml::Element(_, Some(v)) = x else {
-        return None;
+        return Ok(None);
     };
 
     v.iter()
         .find(|n_x| n_x.tag_has_name("properties"))
         .map(|xml_element| match xml_element {
-            Xml::Element(_, Some(props)) => {
-                props.iter().map(parse_tmx_property).collect::<Properties>()
-            }
-            _ => unreachable!(), // This will panic if Xml::Element is not matched
+            Xml::Element(_, Some(props)) => props
+                .iter()
+                .filter_map(|p| match p {
+                    Xml::Element(t, _) => Some(parse_tmx_property(t)),
+                    _ => None,
+                })
+                .collect::<Result<Properties, _>>(),
+            _ => Ok(Properties::default()),
         })
+        .transpose()
 }
 
-fn parse_tmx_property(x: &Xml) -> (String, TiledPropertyType) {
-    let Xml::Element(t, _) = x else { panic!() };
-
-    let v = t.attributes.get("value").unwrap().clone();
+fn parse_tmx_property(t: &Tag) -> Result<(String, TiledPropertyType), TiledParseError> {
+    let v = require::<String>(t, "value")?;
+    let invalid_value = || TiledParseError::Attribute {
+        element: t.into(),
+        attribute: "value".into(),
+    };
 
-    (
-        t.attributes.get("name").unwrap().clone(),
-        match t.attributes.get("type").unwrap().as_str() {
+    Ok((
+        require(t, "name")?,
+        // NOTE:
+        // Tiled omits `type` for `string` properties.
+        match t.attributes.get("type").map_or("string", |s| s.as_str()) {
             "string" => TiledPropertyType::String(v),
-            "int" => TiledPropertyType::Int(v.parse().unwrap()),
-            "float" => TiledPropertyType::Float(v.parse().unwrap()),
-            "bool" => TiledPropertyType::Bool(v.parse().unwrap()),
-            "file" => TiledPropertyType::File(v.parse().unwrap()),
-            "object" => TiledPropertyType::Object(v.parse().unwrap()),
-            _ => unreachable!(),
+            "int" => TiledPropertyType::Int(v.parse().map_err(|_| invalid_value())?),
+            "float" => TiledPropertyType::Float(v.parse().map_err(|_| invalid_value())?),
+            "bool" => TiledPropertyType::Bool(v.parse().map_err(|_| invalid_value())?),
+            "file" => TiledPropertyType::File(v.into()),
+            "object" => TiledPropertyType::Object(v.parse().map_err(|_| invalid_value())?),
+            other => {
+                return Err(TiledParseError::Unsupported {
+                    element: t.into(),
+                    details: format!("properties of type `{other}`"),
+                })
+            }
         },
-    )
+    ))
 }
 
 fn get_parse<T>(hm: &HashMap<String, String>, field: &str) -> Option<T>
@@ -161,35 +197,72 @@ where
     hm.get(field).map(|v| v.parse::<T>().ok()).flatten()
 }
 
-fn parse_layers(v: &Vec<TileSet>, x: &Xml) -> Option<LayerHierarchy> {
-    match x {
+/// The `class` of an element, which used to be its `type` before Tiled 1.9.
+fn class(t: &Tag) -> String {
+    t.attributes
+        .get("class")
+        .or_else(|| t.attributes.get("type"))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Like `get_parse`, but a missing or invalid attribute is an error pointing at the element.
+fn require<T>(t: &Tag, field: &str) -> Result<T, TiledParseError>
+where
+    T: FromStr,
+    <T as FromStr>::Err: Debug,
+{
+    get_parse(&t.attributes, field).ok_or_else(|| TiledParseError::Attribute {
+        element: t.into(),
+        attribute: field.into(),
+    })
+}
+
+fn parse_layers(v: &Vec<TileSet>, x: &Xml) -> Result<Option<LayerHierarchy>, TiledParseError> {
+    let parse_children = |c: &Vec<Xml>| {
+        c.iter()
+            .filter_map(|n_x| parse_layers(v, n_x).transpose())
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    Ok(match x {
         Xml::Element(t, Some(c)) => match t.value.as_str() {
             "group" => Some(LayerHierarchy::Node(
-                TiledLayer::Group(parse_layer(t, ())),
-                c.iter().filter_map(|n_x| parse_layers(v, n_x)).collect(),
+                TiledLayer::Group(parse_layer(t, x, ())?),
+                parse_children(c)?,
             )),
             "map" => Some(LayerHierarchy::Node(
                 TiledLayer::Group(Layer {
                     id: 0,
                     name: "base".into(),
+                    // NOTE:
+                    // The map's own class and properties.
+                    class: class(t),
+                    properties: parse_tmx_properties(x)?.unwrap_or_default(),
                     visible: true,
                     opacity: 1.,
-                    parallax: (0., 0.),
+                    offset: (0., 0.),
+                    parallax: (1., 1.),
                     content: (),
                 }),
-                c.iter().filter_map(|n_x| parse_layers(v, n_x)).collect(),
+                parse_children(c)?,
             )),
             // } else {
             //     LayerHierarchy::Layer(TiledLayer::Group(parse_layer(t)))
             // }),
             "objectgroup" => Some(LayerHierarchy::Leaf(TiledLayer::Object(parse_layer(
                 t,
-                c.iter().filter_map(object_parse).collect(),
-            )))),
+                x,
+                c.iter()
+                    .filter_map(|o| object_parse(o).transpose())
+                    .collect::<Result<_, _>>()?,
+            )?))),
             "layer" => Some(LayerHierarchy::Leaf(TiledLayer::Tile(parse_layer(
                 t,
+                x,
                 grid_parse(
                     v,
+                    t,
                     c.iter()
                         .find(|x| {
                             if let Xml::Element(t, _) = x {
@@ -198,34 +271,55 @@ fn parse_layers(v: &Vec<TileSet>, x: &Xml) -> Option<LayerHierarchy> {
                                 false
                             }
                         })
-                        .unwrap(),
-                ),
-            )))),
-            "imagelayer" => Some(LayerHierarchy::Leaf(TiledLayer::Image(parse_layer(
-                t,
-                todo!(),
-            )))),
+                        .ok_or_else(|| TiledParseError::MissingElement {
+                            parent: t.into(),
+                            element: "data".into(),
+                        })?,
+                )?,
+            )?))),
+            // NOTE:
+            // Image layers are not parsed yet. They are skipped like unknown elements, and listed
+            // by `skipped_layers`.
             _ => None,
         },
         _ => None,
+    })
+}
+
+/// The image layers of the map and its groups, which `parse_layers` leaves out.
+fn skipped_layers(x: &Xml) -> Vec<ElementRef> {
+    let Xml::Element(t, c) = x else {
+        return Vec::new();
+    };
+
+    match (t.value.as_str(), c) {
+        ("imagelayer", _) => vec![t.into()],
+        ("map" | "group", Some(c)) => c.iter().flat_map(skipped_layers).collect(),
+        _ => Vec::new(),
     }
 }
 
-fn grid_parse(v: &Vec<TileSet>, x: &Xml) -> Array2<Option<LayerTile>> {
-    let Xml::Element(t, Some(c)) = x else {
-        panic!()
+fn grid_parse(
+    v: &Vec<TileSet>,
+    layer_tag: &Tag,
+    x: &Xml,
+) -> Result<Array2<Option<LayerTile>>, TiledParseError> {
+    let layer_data_error = |details: String| TiledParseError::LayerData {
+        layer: layer_tag.into(),
+        details,
     };
 
-    let Some(Xml::Text(s)) = c.iter().find(|n_x| !n_x.is_element()) else {
-        panic!("Only csv is supported")
+    let Xml::Element(_, Some(c)) = x else {
+        return Err(layer_data_error("<data> is empty".into()));
     };
 
-    // TODO:
-    // Parse text into vec<gid>
+    let Some(Xml::Text(s)) = c.iter().find(|n_x| !n_x.is_element()) else {
+        return Err(layer_data_error("Only csv is supported".into()));
+    };
 
-    parse_tiles_csv(s.as_str())
-        .unwrap()
-        .map(|gid| parse_tile_from_gid(v, gid))
+    Ok(parse_tiles_csv(s.as_str())
+        .map_err(|e| layer_data_error(format!("{e:?}")))?
+        .map(|gid| parse_tile_from_gid(v, gid)))
 }
 
 // NOTE:
@@ -239,39 +333,35 @@ fn parse_tile(tilesets: &Vec<TileSet>, x: &Xml) -> Option<LayerTile> {
 }
 
 fn parse_tile_from_gid(tilesets: &Vec<TileSet>, bits: &u32) -> Option<LayerTile> {
-    let flags = bits & ALL_FLIP_FLAGS;
-
-    let gid = Gid(bits & !ALL_FLIP_FLAGS);
-    let flip_d = flags & FLIPPED_DIAGONALLY_FLAG == FLIPPED_DIAGONALLY_FLAG; // Swap x and y axis (anti-diagonally) [flips over y = -x line]
-    let flip_h = flags & FLIPPED_HORIZONTALLY_FLAG == FLIPPED_HORIZONTALLY_FLAG; // Flip tile over y axis
-    let flip_v = flags & FLIPPED_VERTICALLY_FLAG == FLIPPED_VERTICALLY_FLAG; // Flip tile over x axis
-
-    if gid == Gid::EMPTY {
-        None
-    } else {
-        Some(LayerTile {
-            tile: gid,
-            flip_h,
-            flip_v,
-            flip_d,
-        })
-    }
+    LayerTile::from_bits(*bits)
 }
 
-fn object_parse(x: &Xml) -> Option<Object> {
-    let Xml::Element(t, c) = x else { return None };
+fn object_parse(x: &Xml) -> Result<Option<Object>, TiledParseError> {
+    let Xml::Element(t, c) = x else {
+        return Ok(None);
+    };
 
     if t.value != "object" {
-        return None;
+        return Ok(None);
     };
 
-    Some(Object {
-        id: get_parse(&t.attributes, "id").unwrap(),
-        // tile_type: get_parse(&t.attributes, "id").unwrap(),
-        position: (
-            get_parse::<f32>(&t.attributes, "x").unwrap(),
-            get_parse::<f32>(&t.attributes, "y").unwrap(),
-        ),
+    let points = |attributes: &HashMap<String, String>| {
+        let invalid_points = || TiledParseError::Attribute {
+            element: t.into(),
+            attribute: "points".into(),
+        };
+
+        attributes
+            .get("points")
+            .ok_or_else(invalid_points)
+            .and_then(|p| parse_spaced_f32_pairs(p).map_err(|_| invalid_points()))
+    };
+
+    Ok(Some(Object {
+        id: require(t, "id")?,
+        name: t.attributes.get("name").cloned().unwrap_or_default(),
+        class: class(t),
+        position: (require::<f32>(t, "x")?, require::<f32>(t, "y")?),
         size: get_parse::<f32>(&t.attributes, "width").and_then(|width| {
             get_parse::<f32>(&t.attributes, "height").map(|height| (width, height))
         }),
@@ -281,7 +371,7 @@ fn object_parse(x: &Xml) -> Option<Object> {
         // Option.
         // We no longer know if it failed to parse due to a parse error, or if the field was
         // missing.
-        tile_global_id: get_parse(&t.attributes, "gid"),
+        tile: get_parse::<u32>(&t.attributes, "gid").and_then(LayerTile::from_bits),
         visible: (get_parse::<u8>(&t.attributes, "visible").unwrap_or(1) == 1),
         otype: match c {
             Some(v) => v
@@ -289,45 +379,36 @@ fn object_parse(x: &Xml) -> Option<Object> {
                 .find_map(|xml_c| {
                     if let Xml::Element(Tag { value, attributes }, _) = xml_c {
                         match value.as_str() {
-                            "ellipse" => Some(ObjectType::Ellipse),
-                            "point" => Some(ObjectType::Point),
-                            "polygon" => Some(ObjectType::Polygon(
-                                parse_spaced_f32_pairs(
-                                    attributes
-                                        .get("points")
-                                        .expect("Polygon should have `points` attribute."),
-                                )
-                                .unwrap(),
-                            )),
-                            "polyline" => Some(ObjectType::Polyline(
-                                parse_spaced_f32_pairs(
-                                    attributes
-                                        .get("points")
-                                        .expect("Polyline should have `points` attribute."),
-                                )
-                                .unwrap(),
-                            )),
+                            "ellipse" => Some(Ok(ObjectType::Ellipse)),
+                            "point" => Some(Ok(ObjectType::Point)),
+                            "polygon" => Some(points(attributes).map(ObjectType::Polygon)),
+                            "polyline" => Some(points(attributes).map(ObjectType::Polyline)),
                             _ => None,
                         }
                     } else {
                         None
                     }
                 })
+                .transpose()?
                 .unwrap_or(ObjectType::Rectangle),
             None => ObjectType::Rectangle,
         }, // If there is no object type in the xml, it's a Rectangle
-        properties: parse_tmx_properties(&x).unwrap_or_default(),
-    })
+        properties: parse_tmx_properties(&x)?.unwrap_or_default(),
+    }))
 }
 
-// TODO:
-// Include `properties`
-fn parse_layer<T>(t: &Tag, content: T) -> Layer<T> {
-    Layer {
-        id: get_parse(&t.attributes, "id").unwrap(),
-        name: t.attributes.get("name").unwrap().clone(),
+fn parse_layer<T>(t: &Tag, x: &Xml, content: T) -> Result<Layer<T>, TiledParseError> {
+    Ok(Layer {
+        id: require(t, "id")?,
+        name: require(t, "name")?,
+        class: class(t),
+        properties: parse_tmx_properties(x)?.unwrap_or_default(),
         visible: (get_parse::<u8>(&t.attributes, "visible").unwrap_or(1) == 1),
         opacity: get_parse(&t.attributes, "opacity").unwrap_or(1.),
+        offset: (
+            get_parse(&t.attributes, "offsetx").unwrap_or(0.),
+            get_parse(&t.attributes, "offsety").unwrap_or(0.),
+        ),
         parallax: (
             get_parse(&t.attributes, "parallaxx").unwrap_or(1.),
             get_parse(&t.attributes, "parallaxy").unwrap_or(1.),
@@ -336,5 +417,5 @@ fn parse_layer<T>(t: &Tag, content: T) -> Layer<T> {
                  // This is probably actually a `1` or `0`, like "visible"
                  // repeatx: get_parse(&t.attributes, "repeatx").unwrap_or(false),
                  // repeaty: get_parse(&t.attributes, "repeaty").unwrap_or(false),
-    }
+    })
 }